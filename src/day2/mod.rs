@@ -1,14 +1,20 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::io::BufRead;
 use std::ops::{Add, AddAssign};
 use std::str::FromStr;
 
 const INPUT: &str = include_str!("input");
 const TEST_INPUT: &str = include_str!("test_input");
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 struct CubeCount {
-    red: u32,
-    green: u32,
-    blue: u32,
+    counts: BTreeMap<String, u32>,
 }
 impl CubeCount {
     pub fn parse(input: &str) -> Self {
@@ -19,37 +25,96 @@ impl CubeCount {
             count = count.trim();
             color = color.trim();
             let count = u32::from_str(count).unwrap();
-            match color {
-                "red" => out.red += count,
-                "green" => out.green += count,
-                "blue" => out.blue += count,
-                _ => panic!("Unknown color: {}", color),
-            }
+            *out.counts.entry(color.to_string()).or_insert(0) += count;
         }
         out
     }
 
-    pub fn power(&self) -> u32 {
-        self.red * self.green * self.blue
+    pub fn get(&self, color: &str) -> u32 {
+        self.counts.get(color).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, color: &str, count: u32) {
+        self.counts.insert(color.to_string(), count);
+    }
+
+    pub fn colors(&self) -> impl Iterator<Item = &str> + '_ {
+        self.counts.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> + '_ {
+        self.counts
+            .iter()
+            .map(|(color, count)| (color.as_str(), *count))
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.values().sum()
+    }
+
+    // Product over every colour in either `colors` or `self`, so a colour missing from `self`
+    // gives 0 and one missing from `colors` still counts. `None` if it doesn't fit in a u64.
+    pub fn power_over(&self, colors: &CubeCount) -> Option<u64> {
+        colors
+            .colors()
+            .chain(self.colors())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .try_fold(1u64, |power, color| {
+                power.checked_mul(self.get(color) as u64)
+            })
+    }
+
+    pub fn fits_within(&self, max: &CubeCount) -> bool {
+        self.iter().all(|(color, count)| count <= max.get(color))
+    }
+
+    pub fn max(&self, other: &CubeCount) -> CubeCount {
+        let mut out = self.clone();
+        for (color, count) in other.iter() {
+            let entry = out.counts.entry(color.to_string()).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+        out
+    }
+}
+impl<S: Into<String>> FromIterator<(S, u32)> for CubeCount {
+    fn from_iter<T: IntoIterator<Item = (S, u32)>>(iter: T) -> Self {
+        let mut out = Self::default();
+        for (color, count) in iter {
+            *out.counts.entry(color.into()).or_insert(0) += count;
+        }
+        out
+    }
+}
+impl Display for CubeCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, (color, count)) in self.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", count, color)?;
+        }
+        Ok(())
     }
 }
 impl Add for CubeCount {
     type Output = CubeCount;
 
-    fn add(self, rhs: Self) -> Self::Output {
-        Self {
-            red: self.red + rhs.red,
-            green: self.green + rhs.green,
-            blue: self.blue + rhs.blue,
-        }
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
     }
 }
 impl AddAssign for CubeCount {
     fn add_assign(&mut self, rhs: Self) {
-        *self = *self + rhs;
+        for (color, count) in rhs.counts {
+            *self.counts.entry(color).or_insert(0) += count;
+        }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Game {
     game_number: u32,
     pulls: Vec<CubeCount>,
@@ -66,46 +131,472 @@ impl Game {
         Self { game_number, pulls }
     }
 
-    pub fn is_possible(&self, max: CubeCount) -> bool {
+    pub fn is_possible(&self, max: &CubeCount) -> bool {
+        self.pulls.iter().all(|pull| pull.fits_within(max))
+    }
+
+    pub fn min_required(&self) -> CubeCount {
+        let mut out = CubeCount::default();
+        for pull in &self.pulls {
+            out = out.max(pull);
+        }
+        out
+    }
+
+    // Each pull is drawn from the bag all at once, so a single pull follows the
+    // multivariate hypergeometric distribution. `replacement` decides whether the bag
+    // is refilled before the next pull.
+    pub fn log_likelihood(&self, bag: &CubeCount, replacement: Replacement) -> f64 {
+        let mut bag = bag.clone();
+        let mut out = 0.0;
         for pull in &self.pulls {
-            if pull.red > max.red || pull.green > max.green || pull.blue > max.blue {
-                return false;
+            if !pull.fits_within(&bag) {
+                return f64::NEG_INFINITY;
+            }
+            out += pull
+                .iter()
+                .map(|(color, count)| ln_choose(bag.get(color), count))
+                .sum::<f64>();
+            out -= ln_choose(bag.total(), pull.total());
+            if replacement == Replacement::None {
+                for (color, count) in pull.iter() {
+                    bag.set(color, bag.get(color) - count);
+                }
             }
         }
-        true
+        out
     }
 
-    pub fn min_required(&self) -> CubeCount {
+    pub fn probability(&self, bag: &CubeCount, replacement: Replacement) -> f64 {
+        self.log_likelihood(bag, replacement).exp()
+    }
+}
+
+impl Display for Game {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Game {}: ", self.game_number)?;
+        for (index, pull) in self.pulls.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", pull)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Replacement {
+    BetweenPulls,
+    None,
+}
+
+fn ln_choose(n: u32, k: u32) -> f64 {
+    if k > n {
+        return f64::NEG_INFINITY;
+    }
+    let k = k.min(n - k);
+    (0..k).map(|i| ((n - i) as f64 / (i + 1) as f64).ln()).sum()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+struct GameLog {
+    games: Vec<Game>,
+}
+impl GameLog {
+    pub fn parse(input: &str) -> Self {
+        Self {
+            games: input.lines().map(Game::parse).collect(),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(input: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(input)
+    }
+
+    pub fn possible_games<'a>(&'a self, bag: &'a CubeCount) -> impl Iterator<Item = &'a Game> + 'a {
+        self.games.iter().filter(move |game| game.is_possible(bag))
+    }
+
+    pub fn minimal_bag(&self) -> CubeCount {
         let mut out = CubeCount::default();
-        for pull in &self.pulls {
-            out.red = out.red.max(pull.red);
-            out.green = out.green.max(pull.green);
-            out.blue = out.blue.max(pull.blue);
+        for game in &self.games {
+            out = out.max(&game.min_required());
+        }
+        out
+    }
+
+    // Finds the bag holding at most `total` cubes that makes the most games possible.
+    // Only colours seen in the log are considered, and any budget left over after the
+    // earlier colours are fixed is spent on the last one.
+    pub fn best_bag_with_total(&self, total: u32) -> (CubeCount, usize) {
+        let colors = self
+            .minimal_bag()
+            .colors()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let requirements = self
+            .games
+            .iter()
+            .map(Game::min_required)
+            .collect::<Vec<_>>();
+        let candidates = requirements.iter().collect::<Vec<_>>();
+        let mut bag = CubeCount::default();
+        let mut best = (CubeCount::default(), 0);
+        Self::search_bags(&colors, &candidates, total, &mut bag, &mut best);
+        best
+    }
+
+    fn search_bags(
+        colors: &[String],
+        candidates: &[&CubeCount],
+        remaining: u32,
+        bag: &mut CubeCount,
+        best: &mut (CubeCount, usize),
+    ) {
+        let Some((color, rest)) = colors.split_first() else {
+            if candidates.len() > best.1 || best.1 == 0 {
+                *best = (bag.clone(), candidates.len());
+            }
+            return;
+        };
+        let mut thresholds = if rest.is_empty() {
+            vec![remaining]
+        } else {
+            candidates
+                .iter()
+                .map(|x| x.get(color))
+                .filter(|x| *x <= remaining)
+                .chain([0])
+                .collect::<Vec<_>>()
+        };
+        thresholds.sort();
+        thresholds.dedup();
+        for threshold in thresholds {
+            let candidates = candidates
+                .iter()
+                .copied()
+                .filter(|x| x.get(color) <= threshold)
+                .collect::<Vec<_>>();
+            if candidates.len() <= best.1 && best.1 != 0 {
+                continue;
+            }
+            bag.set(color, threshold);
+            Self::search_bags(rest, &candidates, remaining - threshold, bag, best);
         }
+        bag.counts.remove(color);
+    }
+
+    // Games possible with `bag` that are not possible once one `color` cube is removed.
+    pub fn ruled_out_by_removing(&self, bag: &CubeCount, color: &str) -> Vec<u32> {
+        let mut smaller = bag.clone();
+        smaller.set(color, bag.get(color).saturating_sub(1));
+        self.possible_games(bag)
+            .filter(|game| !game.is_possible(&smaller))
+            .map(|game| game.game_number)
+            .collect()
+    }
+
+    // Adding a cube can never rule a game out, so this reports the games it rules back in.
+    pub fn ruled_in_by_adding(&self, bag: &CubeCount, color: &str) -> Vec<u32> {
+        let mut larger = bag.clone();
+        larger.set(color, bag.get(color) + 1);
+        self.possible_games(&larger)
+            .filter(|game| !game.is_possible(bag))
+            .map(|game| game.game_number)
+            .collect()
+    }
+
+    pub fn log_likelihood(&self, bag: &CubeCount, replacement: Replacement) -> f64 {
+        self.games
+            .iter()
+            .map(|game| game.log_likelihood(bag, replacement))
+            .sum()
+    }
+
+    // Most likely bag first. Bags that cannot produce the log are dropped.
+    pub fn rank_bags(
+        &self,
+        candidates: impl IntoIterator<Item = CubeCount>,
+        replacement: Replacement,
+    ) -> Vec<(CubeCount, f64)> {
+        let mut out = candidates
+            .into_iter()
+            .map(|bag| {
+                let likelihood = self.log_likelihood(&bag, replacement);
+                (bag, likelihood)
+            })
+            .filter(|(_, likelihood)| likelihood.is_finite())
+            .collect::<Vec<_>>();
+        out.sort_by(|(_, a), (_, b)| b.total_cmp(a));
         out
     }
 }
 
-pub fn day2() {
-    const MAX_CUBES: CubeCount = CubeCount {
-        red: 12,
-        green: 13,
-        blue: 14,
-    };
-    let games = INPUT.lines().map(Game::parse).collect::<Vec<_>>();
+impl Display for GameLog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for game in &self.games {
+            writeln!(f, "{}", game)?;
+        }
+        Ok(())
+    }
+}
 
-    let mut sum = 0;
-    for game in &games {
-        if game.is_possible(MAX_CUBES) {
-            sum += game.game_number;
+// Aggregates over a game log one game at a time, so the log never has to be held in memory.
+#[derive(Debug, Default, Clone)]
+struct GameStats {
+    bag: CubeCount,
+    game_count: u64,
+    pull_count: u64,
+    possible_game_sum: u64,
+    // `None` once the sum overflows.
+    power_sum: Option<u64>,
+    max_per_color: CubeCount,
+    cubes_per_color: BTreeMap<String, u64>,
+    pull_size_histogram: BTreeMap<u32, u64>,
+    color_histograms: BTreeMap<String, BTreeMap<u32, u64>>,
+}
+impl GameStats {
+    pub fn new(bag: CubeCount) -> Self {
+        Self {
+            bag,
+            power_sum: Some(0),
+            ..Self::default()
         }
     }
-    println!("Day 2 part 1: {}", sum);
 
-    let mut sum = 0;
-    for game in games {
+    pub fn from_reader(bag: CubeCount, reader: impl BufRead) -> std::io::Result<Self> {
+        let mut out = Self::new(bag);
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            out.add_game(&Game::parse(&line));
+        }
+        Ok(out)
+    }
+
+    pub fn add_game(&mut self, game: &Game) {
+        self.game_count += 1;
+        if game.is_possible(&self.bag) {
+            self.possible_game_sum += game.game_number as u64;
+        }
         let min_required = game.min_required();
-        sum += min_required.power();
+        self.power_sum = self
+            .power_sum
+            .zip(min_required.power_over(&self.bag))
+            .and_then(|(sum, power)| sum.checked_add(power));
+        self.max_per_color = self.max_per_color.max(&min_required);
+
+        for pull in &game.pulls {
+            self.pull_count += 1;
+            *self.pull_size_histogram.entry(pull.total()).or_insert(0) += 1;
+            for (color, count) in pull.iter() {
+                *self.cubes_per_color.entry(color.to_string()).or_insert(0) += count as u64;
+                *self
+                    .color_histograms
+                    .entry(color.to_string())
+                    .or_default()
+                    .entry(count)
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn part1(&self) -> u64 {
+        self.possible_game_sum
+    }
+
+    pub fn part2(&self) -> Option<u64> {
+        self.power_sum
+    }
+
+    // `None` until a pull has been seen.
+    pub fn mean_pull_size(&self) -> Option<f64> {
+        (self.pull_count > 0)
+            .then(|| self.cubes_per_color.values().sum::<u64>() as f64 / self.pull_count as f64)
+    }
+
+    // Mean number of `color` cubes per pull, counting pulls where it does not appear as zero.
+    pub fn mean_color_count(&self, color: &str) -> Option<f64> {
+        (self.pull_count > 0).then(|| {
+            self.cubes_per_color.get(color).copied().unwrap_or(0) as f64 / self.pull_count as f64
+        })
+    }
+
+    pub fn max_per_color(&self) -> &CubeCount {
+        &self.max_per_color
+    }
+
+    pub fn pull_size_histogram(&self) -> &BTreeMap<u32, u64> {
+        &self.pull_size_histogram
+    }
+
+    pub fn color_histogram(&self, color: &str) -> Option<&BTreeMap<u32, u64>> {
+        self.color_histograms.get(color)
     }
-    println!("Day 2 part 2: {}", sum);
+}
+
+pub fn day2() {
+    let max_cubes = [("red", 12), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect::<CubeCount>();
+    let stats = GameStats::from_reader(max_cubes, INPUT.as_bytes()).unwrap();
+    println!("Day 2 part 1: {}", stats.part1());
+    println!("Day 2 part 2: {}", stats.part2().unwrap());
+}
+
+#[cfg(test)]
+#[test]
+fn display_round_trip_test() {
+    for input in [TEST_INPUT, INPUT] {
+        let log = GameLog::parse(input);
+        let displayed = log.to_string();
+        let reparsed = GameLog::parse(&displayed);
+        assert_eq!(log, reparsed);
+        assert_eq!(displayed, reparsed.to_string());
+    }
+    assert_eq!(
+        Game::parse("Game 1: 3 blue, 4 red; 1 red, 2 green, 6 blue; 2 green").to_string(),
+        "Game 1: 3 blue, 4 red; 6 blue, 2 green, 1 red; 2 green"
+    );
+}
+
+#[cfg(all(test, feature = "serde"))]
+#[test]
+fn json_round_trip_test() {
+    let log = GameLog::parse(TEST_INPUT);
+    let json = log.to_json().unwrap();
+    assert!(json.starts_with(r#"[{"game_number":1,"pulls":[{"blue":3,"red":4}"#));
+    assert_eq!(GameLog::from_json(&json).unwrap(), log);
+}
+
+#[cfg(test)]
+#[test]
+fn game_stats_test() {
+    let max_cubes = [("red", 12), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect::<CubeCount>();
+    let stats = GameStats::from_reader(max_cubes, TEST_INPUT.as_bytes()).unwrap();
+    assert_eq!(stats.part1(), 8);
+    assert_eq!(stats.part2(), Some(2286));
+    assert_eq!(stats.pull_count, 14);
+    assert_eq!(stats.max_per_color().get("red"), 20);
+    assert_eq!(stats.pull_size_histogram().values().sum::<u64>(), 14);
+    assert_eq!(stats.color_histogram("red").unwrap().get(&20), Some(&1));
+    assert!((stats.mean_pull_size().unwrap() * 14.0 - 159.0).abs() < 1e-9);
+
+    let empty = GameStats::from_reader(CubeCount::default(), "\n\n".as_bytes()).unwrap();
+    assert_eq!(empty.mean_pull_size(), None);
+    assert_eq!(empty.mean_color_count("red"), None);
+    assert_eq!(empty.part2(), Some(0));
+}
+
+#[cfg(test)]
+#[test]
+fn power_test() {
+    let bag = [("red", 12), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect::<CubeCount>();
+    let game = Game::parse("Game 1: 3 red; 2 blue");
+    assert_eq!(game.min_required().power_over(&bag), Some(0));
+    assert_eq!(CubeCount::default().power_over(&bag), Some(0));
+    let game = Game::parse("Game 2: 3 red; 2 blue, 1 green");
+    assert_eq!(game.min_required().power_over(&bag), Some(6));
+    let game = Game::parse("Game 3: 2 red, 3 green, 4 blue, 5 yellow");
+    assert_eq!(game.min_required().power_over(&bag), Some(120));
+
+    let stats = GameStats::from_reader(
+        bag.clone(),
+        "Game 1: 3 red; 2 blue\nGame 2: 3 red; 2 blue, 1 green\n".as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(stats.part2(), Some(6));
+
+    // 10000^5 is past u64::MAX.
+    let huge = "Game 1: 10000 red, 10000 green, 10000 blue, 10000 yellow, 10000 pink";
+    let game = Game::parse(huge);
+    assert_eq!(game.min_required().power_over(&bag), None);
+    let stats = GameStats::from_reader(bag, huge.as_bytes()).unwrap();
+    assert_eq!(stats.part2(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn probability_test() {
+    let game = Game::parse("Game 1: 1 red; 1 blue");
+    let bag = [("red", 1), ("blue", 1)].into_iter().collect::<CubeCount>();
+    assert!((game.probability(&bag, Replacement::BetweenPulls) - 0.25).abs() < 1e-9);
+    assert!((game.probability(&bag, Replacement::None) - 0.5).abs() < 1e-9);
+
+    let game = Game::parse("Game 1: 2 red, 1 blue");
+    let bag = [("red", 3), ("blue", 2)].into_iter().collect::<CubeCount>();
+    assert!((game.probability(&bag, Replacement::BetweenPulls) - 0.6).abs() < 1e-9);
+
+    let log = GameLog::parse(TEST_INPUT);
+    let ranked = log.rank_bags(
+        [
+            log.minimal_bag(),
+            log.minimal_bag() + [("red", 20)].into_iter().collect(),
+            [("red", 1)].into_iter().collect(),
+        ],
+        Replacement::BetweenPulls,
+    );
+    assert_eq!(ranked.len(), 2);
+    assert_eq!(ranked[0].0, log.minimal_bag());
+}
+
+#[cfg(test)]
+#[test]
+fn best_bag_with_total_test() {
+    let log = GameLog::parse(TEST_INPUT);
+    let minimal = log.minimal_bag();
+    let (bag, count) = log.best_bag_with_total(minimal.total());
+    assert_eq!(count, log.games.len());
+    assert!(bag.total() <= minimal.total());
+
+    let (bag, count) = log.best_bag_with_total(12 + 13 + 14);
+    assert!(bag.total() <= 12 + 13 + 14);
+    assert_eq!(count, log.possible_games(&bag).count());
+    assert!(count >= 3);
+}
+
+#[cfg(test)]
+#[test]
+fn what_if_test() {
+    let log = GameLog::parse(TEST_INPUT);
+    let minimal = log.minimal_bag();
+    assert_eq!(
+        minimal,
+        [("red", 20), ("green", 13), ("blue", 15)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(log.ruled_out_by_removing(&minimal, "red"), vec![3]);
+    assert_eq!(log.ruled_out_by_removing(&minimal, "green"), vec![3]);
+    assert_eq!(log.ruled_out_by_removing(&minimal, "blue"), vec![4]);
+
+    let bag = [("red", 12), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect::<CubeCount>();
+    assert!(log.ruled_in_by_adding(&bag, "red").is_empty());
+    let bag = [("red", 14), ("green", 13), ("blue", 14)]
+        .into_iter()
+        .collect::<CubeCount>();
+    assert_eq!(log.ruled_in_by_adding(&bag, "blue"), vec![4]);
+    let mut bag = minimal.clone();
+    bag.set("red", 19);
+    assert_eq!(log.ruled_in_by_adding(&bag, "red"), vec![3]);
 }
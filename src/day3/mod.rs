@@ -1,124 +1,515 @@
-use grid::Grid;
+use crate::grid2d::{Grid2d, Pos, ALL_OFFSETS, ORTHOGONAL_OFFSETS};
+use slotmap::{new_key_type, SlotMap};
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write;
+use std::ops::RangeInclusive;
 
 const INPUT: &str = include_str!("input");
 const TEST_INPUT: &str = include_str!("test_input");
 
 #[derive(Debug)]
 pub struct EngineSchematic {
-    grid: Grid<GridCell>,
+    grid: Grid2d<GridCell>,
+    index: PartIndex,
 }
 impl EngineSchematic {
     pub fn parse(input: &str) -> Self {
-        let mut grid = Grid::new(input.lines().count(), input.lines().next().unwrap().len());
-        for (y, line) in input.lines().enumerate() {
-            for (x, char) in line.chars().enumerate() {
-                let cell = match char {
-                    x if x.is_numeric() => GridCell::Number(x.to_digit(10).unwrap()),
-                    '.' => GridCell::Empty,
-                    x => GridCell::Symbol(x),
+        Self::parse_with_diagnostics(input).0
+    }
+
+    // Lines shorter than the widest one are padded with `GridCell::Empty`, and each of them
+    // is reported rather than rejected.
+    pub fn parse_with_diagnostics(input: &str) -> (Self, Vec<ParseDiagnostic>) {
+        let width = input.lines().map(|x| x.chars().count()).max().unwrap_or(0);
+        let mut grid = Grid2d::new(input.lines().count(), width);
+        let mut diagnostics = vec![];
+        for (row, line) in input.lines().enumerate() {
+            let mut line_width = 0;
+            for (col, char) in line.chars().enumerate() {
+                let cell = match char.to_digit(10) {
+                    Some(x) => GridCell::Number(x),
+                    None if char == '.' => GridCell::Empty,
+                    None => GridCell::Symbol(char),
                 };
-                *grid.get_mut(x, y).unwrap() = cell;
+                *grid.get_mut(Pos::new(row, col)).unwrap() = cell;
+                line_width += 1;
+            }
+            if line_width != width {
+                diagnostics.push(ParseDiagnostic::LineWidth {
+                    line: row + 1,
+                    width: line_width,
+                    expected: width,
+                });
             }
         }
-        Self { grid }
+        let index = PartIndex::build(&grid);
+        diagnostics.extend(
+            index
+                .parts()
+                .filter(|(_, x)| x.value.is_none())
+                .map(|(_, x)| ParseDiagnostic::NumberTooWide {
+                    pos: x.pos,
+                    length: x.length,
+                }),
+        );
+        (Self { grid, index }, diagnostics)
     }
 
     pub fn possible_part_numbers(&self) -> Vec<PartNumber> {
+        let mut out = self.index.parts().map(|(_, x)| x).collect::<Vec<_>>();
+        out.sort_by_key(|x| x.pos);
+        out
+    }
+
+    pub fn part_number_near_symbol(&self, part_number: PartNumber) -> bool {
+        let (top_left, bottom_right) = part_number.surrounding();
+        self.grid
+            .region(top_left, bottom_right)
+            .any(|pos| matches!(self.grid.get(pos), Some(GridCell::Symbol(_))))
+    }
+
+    pub fn part_numbers(&self) -> impl Iterator<Item = PartNumber> + '_ {
+        self.possible_part_numbers()
+            .into_iter()
+            .filter(|x| self.part_number_near_symbol(*x))
+    }
+
+    pub fn part_number_sum(&self) -> Result<u128, PartValueError> {
+        checked_sum(self.part_numbers().map(|x| x.value()))
+    }
+
+    pub fn gear_ratio_sum(&self) -> Result<u128, PartValueError> {
+        checked_sum(self.gears().iter().map(Gear::ratio))
+    }
+
+    pub fn gears(&self) -> Vec<Gear> {
+        self.symbols_with_parts(|x| x == '*', PartCount::Exactly(2), Adjacency::Diagonal)
+    }
+
+    // `adjacency` only decides which parts count as near a symbol. Parts are still only the
+    // numbers that `part_numbers` accepts, i.e. next to some symbol in the usual sense.
+    pub fn symbols_with_parts(
+        &self,
+        symbol: impl Fn(char) -> bool,
+        count: PartCount,
+        adjacency: Adjacency,
+    ) -> Vec<Gear> {
+        self.grid
+            .iter()
+            .filter_map(|(pos, cell)| match cell {
+                GridCell::Symbol(x) if symbol(*x) => Some((pos, *x)),
+                _ => None,
+            })
+            .filter_map(|(pos, symbol)| {
+                let parts = self
+                    .index
+                    .parts_near(&self.grid, pos, adjacency)
+                    .into_iter()
+                    .map(|id| self.index.get(id))
+                    .filter(|x| self.part_number_near_symbol(*x))
+                    .collect::<Vec<_>>();
+                count
+                    .matches(parts.len())
+                    .then_some(Gear { parts, pos, symbol })
+            })
+            .collect()
+    }
+}
+
+new_key_type! {
+    pub struct PartId;
+}
+
+// Maps every cell covered by a number to the id of that number, so edits only have to
+// rescan the numbers next to the changed cells.
+#[derive(Debug, Clone)]
+pub struct PartIndex {
+    parts: SlotMap<PartId, PartNumber>,
+    cells: Grid2d<Option<PartId>>,
+}
+impl PartIndex {
+    pub fn build(grid: &Grid2d<GridCell>) -> Self {
+        let mut out = Self {
+            parts: SlotMap::with_key(),
+            cells: Grid2d::new(grid.rows(), grid.cols()),
+        };
+        for row in 0..grid.rows() {
+            for part_number in Self::scan_row(grid, row, 0..=grid.cols().saturating_sub(1)) {
+                out.insert(part_number);
+            }
+        }
+        out
+    }
+
+    pub fn scan_row(
+        grid: &Grid2d<GridCell>,
+        row: usize,
+        cols: RangeInclusive<usize>,
+    ) -> Vec<PartNumber> {
         let mut out = vec![];
-        for (y, row) in self.grid.iter_cols().enumerate() {
-            let mut current_number: Option<PartNumber> = None;
-            for (x, cell) in row.enumerate() {
-                if let GridCell::Number(value) = cell {
-                    if let Some(part_number) = &mut current_number {
-                        part_number.length += 1;
-                        part_number.value *= 10;
-                        part_number.value += *value;
-                    } else {
-                        current_number = Some(PartNumber {
-                            x,
-                            y,
-                            length: 1,
-                            value: *value,
-                        });
-                    }
+        let mut current_number: Option<PartNumber> = None;
+        for (pos, cell) in grid.row(row).filter(|(pos, _)| cols.contains(&pos.col)) {
+            if let GridCell::Number(value) = cell {
+                if let Some(part_number) = &mut current_number {
+                    part_number.length += 1;
+                    part_number.value = part_number
+                        .value
+                        .and_then(|x| x.checked_mul(10)?.checked_add(*value as u128));
                 } else {
-                    if let Some(val) = current_number.take() {
-                        out.push(val);
-                    }
+                    current_number = Some(PartNumber {
+                        pos,
+                        length: 1,
+                        value: Some(*value as u128),
+                    });
                 }
-            }
-            if let Some(val) = current_number {
+            } else if let Some(val) = current_number.take() {
                 out.push(val);
             }
         }
+        if let Some(val) = current_number {
+            out.push(val);
+        }
         out
     }
 
-    pub fn part_number_near_symbol(&self, part_number: PartNumber) -> bool {
-        for x in part_number.x.saturating_sub(1)
-            ..(part_number.x + part_number.length + 1).min(self.grid.rows())
-        {
-            for y in part_number.y.saturating_sub(1)..(part_number.y + 2).min(self.grid.cols()) {
-                if let GridCell::Symbol(_) = self.grid.get(x, y).unwrap() {
-                    return true;
-                }
+    fn insert(&mut self, part_number: PartNumber) {
+        let id = self.parts.insert(part_number);
+        for col in part_number.pos.col..=part_number.last_col() {
+            *self
+                .cells
+                .get_mut(Pos::new(part_number.pos.row, col))
+                .unwrap() = Some(id);
+        }
+    }
+
+    fn remove(&mut self, id: PartId) -> PartNumber {
+        let part_number = self.parts.remove(id).unwrap();
+        for col in part_number.pos.col..=part_number.last_col() {
+            *self
+                .cells
+                .get_mut(Pos::new(part_number.pos.row, col))
+                .unwrap() = None;
+        }
+        part_number
+    }
+
+    // Rescans `start..=end` of `row`, along with any number overlapping or touching it.
+    fn reindex_span(&mut self, grid: &Grid2d<GridCell>, row: usize, start: usize, end: usize) {
+        if row >= grid.rows() || grid.cols() == 0 {
+            return;
+        }
+        let mut start = start.min(grid.cols() - 1);
+        let mut end = end.min(grid.cols() - 1);
+        for col in start.saturating_sub(1)..=end + 1 {
+            if let Some(id) = self.part_at(Pos::new(row, col)) {
+                let part_number = self.remove(id);
+                start = start.min(part_number.pos.col);
+                end = end.max(part_number.last_col());
             }
         }
-        false
+        for part_number in Self::scan_row(grid, row, start..=end) {
+            self.insert(part_number);
+        }
     }
 
-    pub fn part_numbers(&self) -> impl Iterator<Item = PartNumber> + '_ {
-        self.possible_part_numbers()
+    fn ids_in_row(&self, row: usize) -> Vec<PartId> {
+        let mut out = self
+            .cells
+            .row(row)
+            .filter_map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+        out.dedup();
+        out
+    }
+
+    fn shift(&mut self, filter: impl Fn(&PartNumber) -> bool, row: isize, col: isize) {
+        for part_number in self.parts.values_mut().filter(|x| filter(x)) {
+            part_number.pos = part_number.pos.offset(row, col).unwrap();
+        }
+    }
+
+    pub fn get(&self, id: PartId) -> PartNumber {
+        self.parts[id]
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = (PartId, PartNumber)> + '_ {
+        self.parts.iter().map(|(id, x)| (id, *x))
+    }
+
+    pub fn part_at(&self, pos: Pos) -> Option<PartId> {
+        self.cells.get(pos).copied().flatten()
+    }
+
+    // Ids of the numbers adjacent to `pos`, in the order they were first seen. Any digit run
+    // counts here, whether or not it is a valid part number.
+    pub fn parts_near(
+        &self,
+        grid: &Grid2d<GridCell>,
+        pos: Pos,
+        adjacency: Adjacency,
+    ) -> Vec<PartId> {
+        let neighbours: Box<dyn Iterator<Item = Pos>> = match adjacency {
+            Adjacency::Orthogonal => Box::new(grid.neighbours_with(pos, &ORTHOGONAL_OFFSETS)),
+            Adjacency::Diagonal => Box::new(grid.neighbours_with(pos, &ALL_OFFSETS)),
+            Adjacency::Distance(distance) => Box::new(
+                grid.region(
+                    Pos::new(
+                        pos.row.saturating_sub(distance),
+                        pos.col.saturating_sub(distance),
+                    ),
+                    Pos::new(pos.row + distance, pos.col + distance),
+                )
+                .filter(move |x| *x != pos),
+            ),
+        };
+        let mut out = vec![];
+        for id in neighbours.filter_map(|x| self.part_at(x)) {
+            if !out.contains(&id) {
+                out.push(id);
+            }
+        }
+        out
+    }
+}
+
+// Edits return false and leave the schematic untouched when the position is out of range.
+impl EngineSchematic {
+    pub fn set_cell(&mut self, pos: Pos, cell: GridCell) -> bool {
+        let Some(target) = self.grid.get_mut(pos) else {
+            return false;
+        };
+        *target = cell;
+        self.index
+            .reindex_span(&self.grid, pos.row, pos.col, pos.col);
+        true
+    }
+
+    pub fn insert_row(&mut self, row: usize) -> bool {
+        if row > self.grid.rows() {
+            return false;
+        }
+        self.grid.insert_row(row);
+        self.index.cells.insert_row(row);
+        self.index.shift(|x| x.pos.row >= row, 1, 0);
+        true
+    }
+
+    pub fn remove_row(&mut self, row: usize) -> bool {
+        if row >= self.grid.rows() {
+            return false;
+        }
+        for id in self.index.ids_in_row(row) {
+            self.index.remove(id);
+        }
+        self.grid.remove_row(row);
+        self.index.cells.remove_row(row);
+        self.index.shift(|x| x.pos.row > row, -1, 0);
+        true
+    }
+
+    pub fn insert_col(&mut self, col: usize) -> bool {
+        if col > self.grid.cols() {
+            return false;
+        }
+        // Numbers the new column cuts through are dropped and rescanned as two halves.
+        let split = self
+            .index
+            .parts()
+            .filter(|(_, x)| x.pos.col < col && x.last_col() >= col)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        let split = split
             .into_iter()
-            .filter(|x| self.part_number_near_symbol(*x))
+            .map(|id| self.index.remove(id))
+            .collect::<Vec<_>>();
+        self.grid.insert_col(col);
+        self.index.cells.insert_col(col);
+        self.index.shift(|x| x.pos.col >= col, 0, 1);
+        for part_number in split {
+            self.index.reindex_span(
+                &self.grid,
+                part_number.pos.row,
+                part_number.pos.col,
+                part_number.last_col() + 1,
+            );
+        }
+        true
     }
 
-    pub fn gears(&self) -> Vec<Gear> {
-        let part_numbers = self.part_numbers().collect::<Vec<_>>();
-        self.grid
-            .iter_cols()
-            .enumerate()
-            .flat_map(|(y, row)| row.enumerate().map(move |(x, cell)| (x, y, cell)))
-            .filter(|(_, _, cell)| matches!(cell, GridCell::Symbol('*')))
-            .filter_map(|(x, y, _)| {
-                let mut parts = vec![];
-                for part_number in &part_numbers {
-                    // check if part is adjacent/diagonal to symbol
-                    if ((part_number.x as isize - x as isize).abs() <= 1
-                        || ((part_number.x + part_number.length - 1) as isize - x as isize).abs()
-                            <= 1
-                        || (x >= part_number.x && x < part_number.x + part_number.length))
-                        && (part_number.y as isize - y as isize).abs() <= 1
-                    {
-                        parts.push(*part_number);
-                    }
-                }
-                parts
-                    .as_slice()
-                    .try_into()
-                    .ok()
-                    .map(|parts| Gear { parts, x, y })
+    pub fn remove_col(&mut self, col: usize) -> bool {
+        if col >= self.grid.cols() {
+            return false;
+        }
+        let mut spans = vec![(col.saturating_sub(1), col); self.grid.rows()];
+        for (row, span) in spans.iter_mut().enumerate() {
+            if let Some(id) = self.index.part_at(Pos::new(row, col)) {
+                let part_number = self.index.remove(id);
+                span.0 = span.0.min(part_number.pos.col);
+                span.1 = span.1.max(part_number.last_col().saturating_sub(1));
+            }
+        }
+        self.grid.remove_col(col);
+        self.index.cells.remove_col(col);
+        self.index.shift(|x| x.pos.col > col, 0, -1);
+        // The cells on either side of the removed column are now neighbours and may join.
+        for (row, (start, end)) in spans.into_iter().enumerate() {
+            self.index.reindex_span(&self.grid, row, start, end);
+        }
+        true
+    }
+
+    // Total value of the part numbers that are only valid because of the symbol at `pos`.
+    pub fn value_lost_without(&self, pos: Pos) -> Result<u128, PartValueError> {
+        if !matches!(self.grid.get(pos), Some(GridCell::Symbol(_))) {
+            return Ok(0);
+        }
+        let values = self
+            .index
+            .parts_near(&self.grid, pos, Adjacency::Diagonal)
+            .into_iter()
+            .map(|id| self.index.get(id))
+            .filter(|part_number| {
+                let (top_left, bottom_right) = part_number.surrounding();
+                !self.grid.region(top_left, bottom_right).any(|other| {
+                    other != pos && matches!(self.grid.get(other), Some(GridCell::Symbol(_)))
+                })
             })
-            .collect()
+            .map(|x| x.value());
+        checked_sum(values)
+    }
+
+    pub fn most_valuable_symbol(&self) -> Result<Option<(Pos, u128)>, PartValueError> {
+        let mut out = None;
+        for (pos, cell) in self.grid.iter() {
+            if let GridCell::Symbol(_) = cell {
+                let value = self.value_lost_without(pos)?;
+                if out.is_none_or(|(_, best)| value > best) {
+                    out = Some((pos, value));
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseDiagnostic {
+    // `line` is 1-based. `width` and `expected` are counted in chars, not bytes.
+    LineWidth {
+        line: usize,
+        width: usize,
+        expected: usize,
+    },
+    NumberTooWide {
+        pos: Pos,
+        length: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartValueError {
+    // The digits starting at `pos` don't fit in a u128.
+    TooWide { pos: Pos, length: usize },
+    // A sum or product of part values doesn't fit in a u128.
+    Overflow,
+}
+
+fn checked_sum(
+    values: impl IntoIterator<Item = Result<u128, PartValueError>>,
+) -> Result<u128, PartValueError> {
+    let mut out: u128 = 0;
+    for value in values {
+        out = out.checked_add(value?).ok_or(PartValueError::Overflow)?;
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Adjacency {
+    // Shares an edge with the number.
+    Orthogonal,
+    // Shares an edge or a corner with the number.
+    Diagonal,
+    // Within this many cells in both directions, so `Distance(1)` is the same as `Diagonal`.
+    Distance(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartCount {
+    Exactly(usize),
+    AtLeast(usize),
+}
+impl PartCount {
+    pub fn matches(&self, count: usize) -> bool {
+        match self {
+            PartCount::Exactly(x) => count == *x,
+            PartCount::AtLeast(x) => count >= *x,
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PartNumber {
-    x: usize,
-    y: usize,
+    pos: Pos,
     length: usize,
-    value: u32,
+    // `None` when the digits don't fit in a u128.
+    value: Option<u128>,
 }
-#[derive(Debug, Copy, Clone)]
+impl PartNumber {
+    pub fn value(&self) -> Result<u128, PartValueError> {
+        self.value.ok_or(PartValueError::TooWide {
+            pos: self.pos,
+            length: self.length,
+        })
+    }
+
+    pub fn last_col(&self) -> usize {
+        self.pos.col + self.length - 1
+    }
+
+    // Inclusive corners of the box one cell around the number, not clipped to any grid.
+    pub fn surrounding(&self) -> (Pos, Pos) {
+        (
+            Pos::new(
+                self.pos.row.saturating_sub(1),
+                self.pos.col.saturating_sub(1),
+            ),
+            Pos::new(self.pos.row + 1, self.last_col() + 1),
+        )
+    }
+
+    pub fn is_adjacent(&self, pos: Pos, adjacency: Adjacency) -> bool {
+        let row_distance = self.pos.row.abs_diff(pos.row);
+        let col_distance = if pos.col < self.pos.col {
+            self.pos.col - pos.col
+        } else {
+            pos.col.saturating_sub(self.last_col())
+        };
+        match adjacency {
+            Adjacency::Orthogonal => row_distance + col_distance == 1,
+            Adjacency::Diagonal => row_distance.max(col_distance) == 1,
+            Adjacency::Distance(distance) => {
+                let max = row_distance.max(col_distance);
+                max > 0 && max <= distance
+            }
+        }
+    }
+}
+#[derive(Debug, Clone)]
 pub struct Gear {
-    parts: [PartNumber; 2],
-    x: usize,
-    y: usize,
+    parts: Vec<PartNumber>,
+    pos: Pos,
+    symbol: char,
 }
 impl Gear {
-    pub fn ratio(&self) -> u32 {
-        self.parts[0].value * self.parts[1].value
+    pub fn ratio(&self) -> Result<u128, PartValueError> {
+        let mut out: u128 = 1;
+        for part_number in &self.parts {
+            out = out
+                .checked_mul(part_number.value()?)
+                .ok_or(PartValueError::Overflow)?;
+        }
+        Ok(out)
     }
 }
 
@@ -133,14 +524,470 @@ impl GridCell {
     pub fn is_number(&self) -> bool {
         matches!(self, Self::Number(_))
     }
+
+    pub fn to_char(&self) -> char {
+        match self {
+            GridCell::Empty => '.',
+            GridCell::Symbol(x) => *x,
+            GridCell::Number(x) => char::from_digit(*x, 10).unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    parts: Vec<PartNumber>,
+    symbols: Vec<(Pos, char)>,
+    top_left: Pos,
+    bottom_right: Pos,
+}
+impl Cluster {
+    pub fn value(&self) -> Result<u128, PartValueError> {
+        checked_sum(self.parts.iter().map(PartNumber::value))
+    }
+
+    // Inclusive corners of the smallest box holding every member.
+    pub fn bounding_box(&self) -> (Pos, Pos) {
+        (self.top_left, self.bottom_right)
+    }
+
+    fn include(&mut self, pos: Pos) {
+        self.top_left = Pos::new(
+            self.top_left.row.min(pos.row),
+            self.top_left.col.min(pos.col),
+        );
+        self.bottom_right = Pos::new(
+            self.bottom_right.row.max(pos.row),
+            self.bottom_right.col.max(pos.col),
+        );
+    }
+}
+
+impl EngineSchematic {
+    pub fn symbols_near_part(&self, part_number: PartNumber, adjacency: Adjacency) -> Vec<Pos> {
+        let reach = match adjacency {
+            Adjacency::Orthogonal | Adjacency::Diagonal => 1,
+            Adjacency::Distance(distance) => distance,
+        };
+        self.grid
+            .region(
+                Pos::new(
+                    part_number.pos.row.saturating_sub(reach),
+                    part_number.pos.col.saturating_sub(reach),
+                ),
+                Pos::new(part_number.pos.row + reach, part_number.last_col() + reach),
+            )
+            .filter(|pos| matches!(self.grid.get(*pos), Some(GridCell::Symbol(_))))
+            .filter(|pos| part_number.is_adjacent(*pos, adjacency))
+            .collect()
+    }
+
+    // Groups parts and symbols that are linked through a chain of part/symbol adjacencies.
+    // Orphan numbers and lone symbols end up in clusters of their own.
+    pub fn clusters(&self, adjacency: Adjacency) -> Vec<Cluster> {
+        #[derive(Copy, Clone)]
+        enum Node {
+            Part(PartId),
+            Symbol(Pos),
+        }
+
+        let mut seen_parts = HashSet::new();
+        let mut seen_symbols = HashSet::new();
+        let mut out = vec![];
+        for (pos, cell) in self.grid.iter() {
+            let start = match (cell, self.index.part_at(pos)) {
+                (GridCell::Symbol(_), _) if !seen_symbols.contains(&pos) => Node::Symbol(pos),
+                (GridCell::Number(_), Some(id)) if !seen_parts.contains(&id) => Node::Part(id),
+                _ => continue,
+            };
+            match start {
+                Node::Part(id) => {
+                    seen_parts.insert(id);
+                }
+                Node::Symbol(pos) => {
+                    seen_symbols.insert(pos);
+                }
+            }
+
+            let mut cluster = Cluster {
+                parts: vec![],
+                symbols: vec![],
+                top_left: pos,
+                bottom_right: pos,
+            };
+            let mut queue = VecDeque::from([start]);
+            while let Some(node) = queue.pop_front() {
+                match node {
+                    Node::Part(id) => {
+                        let part_number = self.index.get(id);
+                        cluster.include(part_number.pos);
+                        cluster.include(Pos::new(part_number.pos.row, part_number.last_col()));
+                        cluster.parts.push(part_number);
+                        for symbol in self.symbols_near_part(part_number, adjacency) {
+                            if seen_symbols.insert(symbol) {
+                                queue.push_back(Node::Symbol(symbol));
+                            }
+                        }
+                    }
+                    Node::Symbol(pos) => {
+                        cluster.include(pos);
+                        cluster
+                            .symbols
+                            .push((pos, self.grid.get(pos).unwrap().to_char()));
+                        for id in self.index.parts_near(&self.grid, pos, adjacency) {
+                            if seen_parts.insert(id) {
+                                queue.push_back(Node::Part(id));
+                            }
+                        }
+                    }
+                }
+            }
+            out.push(cluster);
+        }
+        out
+    }
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_PART: &str = "\x1b[32m";
+const ANSI_ORPHAN: &str = "\x1b[31m";
+const ANSI_GEAR: &str = "\x1b[1;33m";
+const ANSI_SYMBOL: &str = "\x1b[36m";
+const ANSI_DIM: &str = "\x1b[2m";
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RenderOptions {
+    pub color: bool,
+    pub rulers: bool,
+    // Inclusive corners of the area to draw, clipped to the grid. `None` draws everything.
+    pub viewport: Option<(Pos, Pos)>,
+}
+
+impl EngineSchematic {
+    pub fn render(&self, options: RenderOptions) -> String {
+        let (top_left, bottom_right) = options.viewport.unwrap_or((
+            Pos::new(0, 0),
+            Pos::new(
+                self.grid.rows().saturating_sub(1),
+                self.grid.cols().saturating_sub(1),
+            ),
+        ));
+        let bottom_right = Pos::new(
+            bottom_right.row.min(self.grid.rows().saturating_sub(1)),
+            bottom_right.col.min(self.grid.cols().saturating_sub(1)),
+        );
+        let valid_parts = self
+            .index
+            .parts()
+            .filter(|(_, part)| self.part_number_near_symbol(*part))
+            .map(|(id, _)| id)
+            .collect::<HashSet<_>>();
+        let gears = self
+            .gears()
+            .into_iter()
+            .map(|x| x.pos)
+            .collect::<HashSet<_>>();
+        let gutter = bottom_right.row.to_string().len();
+
+        let mut out = String::new();
+        if options.rulers {
+            // One row per digit of the widest column number, most significant first.
+            let digits = bottom_right.col.to_string().len() as u32;
+            for divisor in (0..digits).rev().map(|x| 10usize.pow(x)) {
+                write!(out, "{:gutter$} ", "").unwrap();
+                for col in top_left.col..=bottom_right.col {
+                    out.push(char::from_digit((col / divisor % 10) as u32, 10).unwrap());
+                }
+                out.push('\n');
+            }
+        }
+        let mut current_row = None;
+        for pos in self.grid.region(top_left, bottom_right) {
+            if current_row != Some(pos.row) {
+                if current_row.is_some() {
+                    out.push('\n');
+                }
+                current_row = Some(pos.row);
+                if options.rulers {
+                    write!(out, "{:>gutter$} ", pos.row).unwrap();
+                }
+            }
+            let cell = self.grid.get(pos).unwrap();
+            if !options.color {
+                out.push(cell.to_char());
+                continue;
+            }
+            let color = match cell {
+                GridCell::Empty => ANSI_DIM,
+                GridCell::Number(_) => match self.index.part_at(pos) {
+                    Some(id) if valid_parts.contains(&id) => ANSI_PART,
+                    _ => ANSI_ORPHAN,
+                },
+                GridCell::Symbol(_) if gears.contains(&pos) => ANSI_GEAR,
+                GridCell::Symbol(_) => ANSI_SYMBOL,
+            };
+            write!(out, "{}{}{}", color, cell.to_char(), ANSI_RESET).unwrap();
+        }
+        out.push('\n');
+        out
+    }
 }
 
 pub fn day3() {
     let engine = EngineSchematic::parse(INPUT);
-    let sum = engine.part_numbers().map(|x| x.value).sum::<u32>();
-    println!("Day 3 part 1: {}", sum);
+    println!("Day 3 part 1: {}", engine.part_number_sum().unwrap());
+    println!("Day 3 part 2: {}", engine.gear_ratio_sum().unwrap());
+}
+
+#[cfg(test)]
+#[test]
+fn incremental_edit_test() {
+    fn assert_matches_fresh_parse(engine: &EngineSchematic) {
+        let fresh = EngineSchematic::parse(&engine.render(RenderOptions::default()));
+        assert_eq!(
+            engine.possible_part_numbers(),
+            fresh.possible_part_numbers()
+        );
+        assert_eq!(
+            engine.part_numbers().collect::<Vec<_>>(),
+            fresh.part_numbers().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            engine.gears().iter().map(Gear::ratio).collect::<Vec<_>>(),
+            fresh.gears().iter().map(Gear::ratio).collect::<Vec<_>>()
+        );
+    }
+
+    let mut engine = EngineSchematic::parse(TEST_INPUT);
+    engine.set_cell(Pos::new(0, 3), GridCell::Number(1));
+    assert_matches_fresh_parse(&engine);
+    assert!(engine
+        .possible_part_numbers()
+        .iter()
+        .any(|x| x.value() == Ok(4671)));
+    engine.set_cell(Pos::new(2, 4), GridCell::Number(9));
+    assert_matches_fresh_parse(&engine);
+    engine.set_cell(Pos::new(2, 4), GridCell::Empty);
+    assert_matches_fresh_parse(&engine);
+    engine.set_cell(Pos::new(0, 1), GridCell::Symbol('*'));
+    assert_matches_fresh_parse(&engine);
+
+    engine.insert_col(7);
+    assert_matches_fresh_parse(&engine);
+    engine.remove_col(7);
+    assert_matches_fresh_parse(&engine);
+    engine.remove_col(9);
+    engine.remove_col(4);
+    assert_matches_fresh_parse(&engine);
+    engine.insert_row(3);
+    assert_matches_fresh_parse(&engine);
+    engine.remove_row(0);
+    assert_matches_fresh_parse(&engine);
+
+    let (rows, cols) = (engine.grid.rows(), engine.grid.cols());
+    assert!(!engine.set_cell(Pos::new(rows, 0), GridCell::Symbol('*')));
+    assert!(!engine.set_cell(Pos::new(0, cols), GridCell::Symbol('*')));
+    assert!(!engine.remove_row(rows));
+    assert!(!engine.remove_col(cols));
+    assert!(!engine.insert_row(rows + 1));
+    assert!(!engine.insert_col(cols + 1));
+    assert_eq!((engine.grid.rows(), engine.grid.cols()), (rows, cols));
+    assert_matches_fresh_parse(&engine);
+    assert!(engine.insert_col(cols));
+    assert!(engine.remove_col(cols));
+    assert_matches_fresh_parse(&engine);
+
+    let engine = EngineSchematic::parse(TEST_INPUT);
+    assert_eq!(engine.value_lost_without(Pos::new(1, 3)), Ok(467 + 35));
+    assert_eq!(
+        engine.most_valuable_symbol(),
+        Ok(Some((Pos::new(8, 5), 755 + 598)))
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn ragged_parse_test() {
+    let (engine, diagnostics) = EngineSchematic::parse_with_diagnostics("467\n·é*..\n35");
+    assert_eq!(engine.grid.cols(), 5);
+    assert_eq!(
+        diagnostics,
+        vec![
+            ParseDiagnostic::LineWidth {
+                line: 1,
+                width: 3,
+                expected: 5
+            },
+            ParseDiagnostic::LineWidth {
+                line: 3,
+                width: 2,
+                expected: 5
+            },
+        ]
+    );
+    assert!(matches!(
+        engine.grid.get(Pos::new(1, 1)),
+        Some(GridCell::Symbol('é'))
+    ));
+    assert!(matches!(
+        engine.grid.get(Pos::new(2, 4)),
+        Some(GridCell::Empty)
+    ));
+    assert_eq!(engine.part_number_sum().unwrap(), 467 + 35);
+
+    let (_, diagnostics) = EngineSchematic::parse_with_diagnostics(TEST_INPUT);
+    assert!(diagnostics.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn wide_value_test() {
+    let wide = format!("3{}", "0".repeat(38));
+    let too_wide = "9".repeat(40);
+    let (engine, diagnostics) =
+        EngineSchematic::parse_with_diagnostics(&format!("{wide}*\n{too_wide}"));
+    assert_eq!(
+        diagnostics,
+        vec![ParseDiagnostic::NumberTooWide {
+            pos: Pos::new(1, 0),
+            length: 40
+        }]
+    );
+    assert_eq!(
+        engine.part_number_sum(),
+        Err(PartValueError::TooWide {
+            pos: Pos::new(1, 0),
+            length: 40
+        })
+    );
+    assert_eq!(
+        engine.possible_part_numbers()[0].value(),
+        Ok(wide.parse().unwrap())
+    );
+
+    let engine = EngineSchematic::parse(&format!("{wide}*99\n\n{wide}#"));
+    assert_eq!(engine.part_number_sum(), Err(PartValueError::Overflow));
+    assert_eq!(engine.gear_ratio_sum(), Err(PartValueError::Overflow));
+}
+
+#[cfg(test)]
+#[test]
+fn clusters_test() {
+    let engine = EngineSchematic::parse(TEST_INPUT);
+    let clusters = engine.clusters(Adjacency::Diagonal);
+    let linked = clusters
+        .iter()
+        .filter(|x| !x.parts.is_empty() && !x.symbols.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(linked.len(), 6);
+    assert_eq!(checked_sum(linked.iter().map(|x| x.value())).unwrap(), 4361);
+    assert_eq!(clusters.len(), 8);
+
+    let first = linked[0];
+    assert_eq!(first.value(), Ok(467 + 35));
+    assert_eq!(first.bounding_box(), (Pos::new(0, 0), Pos::new(2, 3)));
+}
+
+#[cfg(test)]
+#[test]
+fn render_test() {
+    let engine = EngineSchematic::parse(TEST_INPUT);
+    let plain = engine.render(RenderOptions::default());
+    assert_eq!(plain, format!("{}\n", TEST_INPUT.trim_end()));
+
+    let cropped = engine.render(RenderOptions {
+        color: false,
+        rulers: true,
+        viewport: Some((Pos::new(0, 1), Pos::new(1, 20))),
+    });
+    assert_eq!(cropped, "  123456789\n0 67..114..\n1 ..*......\n");
+
+    let wide = EngineSchematic::parse(&format!("{}12*", ".".repeat(1003)));
+    let cropped = wide.render(RenderOptions {
+        color: false,
+        rulers: true,
+        viewport: Some((Pos::new(0, 1003), Pos::new(0, 1005))),
+    });
+    assert_eq!(cropped, "  111\n  000\n  000\n  345\n0 12*\n");
+
+    let colored = engine.render(RenderOptions {
+        color: true,
+        rulers: false,
+        viewport: Some((Pos::new(0, 0), Pos::new(1, 7))),
+    });
+    assert!(colored.starts_with(&format!("{}4{}", ANSI_PART, ANSI_RESET)));
+    assert!(colored.contains(&format!("{}1{}", ANSI_ORPHAN, ANSI_RESET)));
+    assert!(colored.contains(&format!("{}*{}", ANSI_GEAR, ANSI_RESET)));
+}
+
+#[cfg(test)]
+#[test]
+fn non_square_test() {
+    let engine = EngineSchematic::parse(TEST_INPUT);
+    assert_eq!(engine.part_number_sum().unwrap(), 4361);
+    assert_eq!(engine.gear_ratio_sum().unwrap(), 467835);
+
+    let engine = EngineSchematic::parse("467..114......\n...*.......*..\n..35..633...12");
+    assert_eq!(engine.grid.rows(), 3);
+    assert_eq!(engine.grid.cols(), 14);
+    assert_eq!(engine.part_number_sum().unwrap(), 467 + 35 + 12);
+    assert_eq!(engine.gear_ratio_sum().unwrap(), 467 * 35);
+}
+
+#[cfg(test)]
+#[test]
+fn symbols_with_parts_test() {
+    let engine = EngineSchematic::parse(TEST_INPUT);
+    let orthogonal =
+        engine.symbols_with_parts(|x| x == '*', PartCount::AtLeast(1), Adjacency::Orthogonal);
+    let values = orthogonal
+        .iter()
+        .flat_map(|x| x.parts.iter().map(|x| x.value().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![35, 617, 598]);
+
+    let any = engine.symbols_with_parts(|_| true, PartCount::AtLeast(1), Adjacency::Diagonal);
+    assert_eq!(any.len(), 6);
+    let single = engine.symbols_with_parts(|_| true, PartCount::Exactly(1), Adjacency::Diagonal);
+    assert_eq!(single.len(), 4);
+    let far =
+        engine.symbols_with_parts(|x| x == '$', PartCount::AtLeast(3), Adjacency::Distance(2));
+    assert_eq!(far.len(), 1);
+    assert_eq!(far[0].parts.len(), 3);
+
+    let orphan = EngineSchematic::parse("1..\n...\n..*");
+    assert_eq!(orphan.part_numbers().count(), 0);
+    let far = orphan.symbols_with_parts(|_| true, PartCount::AtLeast(0), Adjacency::Distance(2));
+    assert_eq!(far.len(), 1);
+    assert!(far[0].parts.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn part_index_test() {
+    let engine = EngineSchematic::parse("123.\n...*\n45.6");
+    let index = PartIndex::build(&engine.grid);
+    let values = |ids: Vec<PartId>| {
+        ids.into_iter()
+            .map(|id| index.get(id).value().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let first = index.part_at(Pos::new(0, 0)).unwrap();
+    assert_eq!(index.part_at(Pos::new(0, 2)), Some(first));
+    assert_eq!(index.get(first).length, 3);
+    assert_eq!(index.part_at(Pos::new(0, 3)), None);
+    assert_eq!(index.part_at(Pos::new(1, 3)), None);
+    assert_eq!(index.part_at(Pos::new(3, 0)), None);
+    assert_eq!(index.parts().count(), 3);
 
-    let gears = engine.gears();
-    let sum = gears.iter().map(Gear::ratio).sum::<u32>();
-    println!("Day 3 part 2: {}", sum);
+    let near = |pos, adjacency| values(index.parts_near(&engine.grid, pos, adjacency));
+    assert_eq!(near(Pos::new(1, 3), Adjacency::Diagonal), vec![123, 6]);
+    assert_eq!(near(Pos::new(1, 3), Adjacency::Orthogonal), vec![6]);
+    assert_eq!(near(Pos::new(1, 0), Adjacency::Diagonal), vec![123, 45]);
+    assert_eq!(near(Pos::new(1, 2), Adjacency::Orthogonal), vec![123]);
+    assert_eq!(
+        near(Pos::new(0, 0), Adjacency::Distance(5)),
+        vec![123, 45, 6]
+    );
 }
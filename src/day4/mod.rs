@@ -1,46 +1,97 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
 const INPUT: &str = include_str!("input");
 const TEST_INPUT: &str = include_str!("test_input");
 
+// Numbers below 128 live in a bitset; anything larger falls back to a hash set.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NumberSet {
+    small: u128,
+    large: HashSet<u32>,
+}
+impl NumberSet {
+    pub fn insert(&mut self, number: u32) {
+        if number < u128::BITS {
+            self.small |= 1 << number;
+        } else {
+            self.large.insert(number);
+        }
+    }
+
+    pub fn contains(&self, number: u32) -> bool {
+        if number < u128::BITS {
+            self.small & (1 << number) != 0
+        } else {
+            self.large.contains(&number)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.small.count_ones() as usize + self.large.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..u128::BITS)
+            .filter(|x| self.small & (1 << x) != 0)
+            .chain(self.large.iter().copied())
+    }
+
+    pub fn intersection_count(&self, other: &NumberSet) -> usize {
+        let (smaller, larger) = if self.large.len() <= other.large.len() {
+            (&self.large, &other.large)
+        } else {
+            (&other.large, &self.large)
+        };
+        (self.small & other.small).count_ones() as usize
+            + smaller.iter().filter(|x| larger.contains(x)).count()
+    }
+}
+impl FromIterator<u32> for NumberSet {
+    fn from_iter<T: IntoIterator<Item = u32>>(iter: T) -> Self {
+        let mut out = Self::default();
+        for number in iter {
+            out.insert(number);
+        }
+        out
+    }
+}
+
 #[derive(Debug)]
 pub struct Card {
     id: u32,
-    winning_numbers: Vec<u32>,
-    my_numbers: Vec<u32>,
+    winning_numbers: NumberSet,
+    my_numbers: NumberSet,
 }
 impl Card {
     pub fn parse(input: &str) -> Self {
         let (card, numbers) = input.split_at(input.find(':').unwrap());
-        let numbers = numbers.split_at(1).1.trim();
-        let (card, id) = card.split_at(5);
-        assert_eq!("Card ", card);
+        let numbers = numbers.split_at(1).1;
+        let id = card.strip_prefix("Card").unwrap();
         let (winning, my) = numbers.split_at(numbers.find('|').unwrap());
-        let winning = winning.trim();
-        let my = my.split_at(1).1.trim();
+        let my = my.split_at(1).1;
         Self {
             id: id.trim().parse().unwrap(),
             winning_numbers: winning
-                .trim()
-                .replace("  ", " ")
-                .split(' ')
-                .map(|x| x.trim().parse().unwrap())
-                .collect(),
-            my_numbers: my
-                .trim()
-                .replace("  ", " ")
-                .split(' ')
-                .map(|x| x.trim().parse().unwrap())
+                .split_whitespace()
+                .map(|x| x.parse().unwrap())
                 .collect(),
+            my_numbers: my.split_whitespace().map(|x| x.parse().unwrap()).collect(),
         }
     }
 
+    pub fn matching_numbers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.my_numbers
+            .iter()
+            .filter(|x| self.winning_numbers.contains(*x))
+    }
+
     pub fn winning_count(&self) -> u32 {
-        let mut winning_count: u32 = 0;
-        for my_number in &self.my_numbers {
-            if self.winning_numbers.contains(my_number) {
-                winning_count += 1;
-            }
-        }
-        winning_count
+        self.winning_numbers.intersection_count(&self.my_numbers) as u32
     }
 
     pub fn value(&self) -> u32 {
@@ -51,20 +102,332 @@ impl Card {
     }
 }
 
+// Decides which cards an instance of a card copies. The cascade runs once in deck order, so a
+// copy of a card that has already been processed is counted but copies nothing itself.
+pub trait CascadeRule {
+    fn targets(&self, cards: &[Card], index: usize) -> Vec<usize>;
+
+    // Upper bound on the instances of any one card. Copies beyond it are dropped.
+    fn cap(&self) -> Option<u128> {
+        None
+    }
+}
+
+// The puzzle rule: copy the next `winning_count` cards, stopping at the end of the deck.
+#[derive(Debug, Copy, Clone)]
+pub struct NextCards;
+impl CascadeRule for NextCards {
+    fn targets(&self, cards: &[Card], index: usize) -> Vec<usize> {
+        ((index + 1).min(cards.len())
+            ..(index + 1 + cards[index].winning_count() as usize).min(cards.len()))
+            .collect()
+    }
+}
+
+// Like `NextCards`, but running off the end of the deck continues from the start.
+#[derive(Debug, Copy, Clone)]
+pub struct WrapAround;
+impl CascadeRule for WrapAround {
+    fn targets(&self, cards: &[Card], index: usize) -> Vec<usize> {
+        let count = (cards[index].winning_count() as usize).min(cards.len());
+        (1..=count)
+            .map(|offset| (index + offset) % cards.len())
+            .collect()
+    }
+}
+
+// Copies the cards whose ids are the matching numbers themselves. Numbers without a card
+// are ignored. Built from the deck it will be used with, so lookups don't scan the deck.
+#[derive(Debug, Clone)]
+pub struct ByMatchingNumbers {
+    // Position of the first card with each id.
+    indexes: HashMap<u32, usize>,
+}
+impl ByMatchingNumbers {
+    pub fn new(cards: &[Card]) -> Self {
+        let mut indexes = HashMap::new();
+        for (index, card) in cards.iter().enumerate() {
+            indexes.entry(card.id).or_insert(index);
+        }
+        Self { indexes }
+    }
+}
+impl CascadeRule for ByMatchingNumbers {
+    fn targets(&self, cards: &[Card], index: usize) -> Vec<usize> {
+        let mut out = cards[index]
+            .matching_numbers()
+            .filter_map(|number| self.indexes.get(&number).copied())
+            .collect::<Vec<_>>();
+        out.sort();
+        out
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Capped<R> {
+    pub rule: R,
+    pub cap: u128,
+}
+impl<R: CascadeRule> CascadeRule for Capped<R> {
+    fn targets(&self, cards: &[Card], index: usize) -> Vec<usize> {
+        self.rule.targets(cards, index)
+    }
+
+    fn cap(&self) -> Option<u128> {
+        Some(self.rule.cap().map_or(self.cap, |x| x.min(self.cap)))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CopyOverflow {
+    // Id of the card whose count no longer fits in a u128.
+    pub card: u32,
+}
+
+// How the part 2 copy cascade played out, card by card. Indexes are positions in the deck.
+#[derive(Debug, Clone)]
+pub struct Cascade {
+    ids: Vec<u32>,
+    copies: Vec<u128>,
+    sources: Vec<Vec<(usize, u128)>>,
+    descendants: Vec<u128>,
+    total: u128,
+}
+impl Cascade {
+    pub fn new(cards: &[Card], rule: &impl CascadeRule) -> Result<Self, CopyOverflow> {
+        let targets = (0..cards.len())
+            .map(|index| rule.targets(cards, index))
+            .collect::<Vec<_>>();
+        let overflow = |index: usize| CopyOverflow {
+            card: cards[index].id,
+        };
+
+        let mut copies = vec![1u128; cards.len()];
+        if let Some(cap) = rule.cap() {
+            copies.iter_mut().for_each(|x| *x = (*x).min(cap));
+        }
+        let mut sources = vec![vec![]; cards.len()];
+        for (index, targets) in targets.iter().enumerate() {
+            let multiplier = copies[index];
+            for target in targets.iter().copied() {
+                let mut received = multiplier;
+                if let Some(cap) = rule.cap() {
+                    received = received.min(cap.saturating_sub(copies[target]));
+                }
+                if received == 0 {
+                    continue;
+                }
+                copies[target] = copies[target]
+                    .checked_add(received)
+                    .ok_or_else(|| overflow(target))?;
+                sources[target].push((index, received));
+            }
+        }
+
+        // Only copies of later cards cascade, so walking backwards sees every such target
+        // first. This is what one instance can win, so it ignores any cap.
+        let mut descendants = vec![0u128; cards.len()];
+        for index in (0..cards.len()).rev() {
+            let mut total: u128 = 0;
+            for target in targets[index].iter().copied() {
+                let won = if target > index {
+                    descendants[target]
+                        .checked_add(1)
+                        .ok_or_else(|| overflow(index))?
+                } else {
+                    1
+                };
+                total = total.checked_add(won).ok_or_else(|| overflow(index))?;
+            }
+            descendants[index] = total;
+        }
+
+        let mut total: u128 = 0;
+        for (index, count) in copies.iter().enumerate() {
+            total = total.checked_add(*count).ok_or_else(|| overflow(index))?;
+        }
+
+        Ok(Self {
+            ids: cards.iter().map(|x| x.id).collect(),
+            copies,
+            sources,
+            descendants,
+            total,
+        })
+    }
+
+    pub fn total_cards(&self) -> u128 {
+        self.total
+    }
+
+    // Instances of the card at `index`, including the original.
+    pub fn copies(&self, index: usize) -> u128 {
+        self.copies[index]
+    }
+
+    // The cards that copied `index`, and how many copies each one handed over.
+    pub fn sources(&self, index: usize) -> &[(usize, u128)] {
+        &self.sources[index]
+    }
+
+    // Cards won, directly or through later copies, by a single instance of `index`.
+    pub fn descendants(&self, index: usize) -> u128 {
+        self.descendants[index]
+    }
+
+    pub fn table(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "card\tcopies\tdescendants\tsources").unwrap();
+        for index in 0..self.ids.len() {
+            let sources = self.sources[index]
+                .iter()
+                .map(|(source, copies)| format!("{}x{}", self.ids[*source], copies))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                self.ids[index], self.copies[index], self.descendants[index], sources
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph cascade {{").unwrap();
+        for (index, id) in self.ids.iter().enumerate() {
+            writeln!(
+                out,
+                "    card{} [label=\"Card {}\\n{} copies\"];",
+                id, id, self.copies[index]
+            )
+            .unwrap();
+        }
+        for (index, sources) in self.sources.iter().enumerate() {
+            for (source, copies) in sources {
+                writeln!(
+                    out,
+                    "    card{} -> card{} [label=\"{}\"];",
+                    self.ids[*source], self.ids[index], copies
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
 pub fn day4() {
     let cards = INPUT.lines().map(Card::parse).collect::<Vec<_>>();
     let sum = cards.iter().map(Card::value).sum::<u32>();
     println!("Day 4 part 1: {}", sum);
 
-    let mut card_multipliers = (0..cards.len()).map(|_| 1u32).collect::<Vec<_>>();
-    for (index, card) in cards.iter().enumerate() {
-        let multiplier = card_multipliers[index];
-        for other_index in (index + 1).min(card_multipliers.len())
-            ..=(index + card.winning_count() as usize).min(card_multipliers.len() - 1)
-        {
-            card_multipliers[other_index] += multiplier;
-        }
-    }
-    let sum = card_multipliers.iter().sum::<u32>();
-    println!("Day 4 part 2: {}", sum);
+    let cascade = Cascade::new(&cards, &NextCards).unwrap();
+    println!("Day 4 part 2: {}", cascade.total_cards());
+}
+
+#[cfg(test)]
+#[test]
+fn winning_count_test() {
+    let counts = TEST_INPUT
+        .lines()
+        .map(|x| Card::parse(x).winning_count())
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![4, 2, 2, 1, 0, 0]);
+
+    let card = Card::parse("Card   7:  1 200\t130 |   130  5 1   999 200");
+    assert_eq!(card.id, 7);
+    assert_eq!(card.winning_count(), 3);
+    assert_eq!(card.value(), 4);
+}
+
+#[cfg(test)]
+#[test]
+fn cascade_test() {
+    let cards = TEST_INPUT.lines().map(Card::parse).collect::<Vec<_>>();
+    let cascade = Cascade::new(&cards, &NextCards).unwrap();
+    assert_eq!(cascade.total_cards(), 30);
+    assert_eq!(
+        (0..cards.len())
+            .map(|x| cascade.copies(x))
+            .collect::<Vec<_>>(),
+        vec![1, 2, 4, 8, 14, 1]
+    );
+    assert_eq!(cascade.sources(3), &[(0, 1), (1, 2), (2, 4)]);
+    assert_eq!(
+        (0..cards.len())
+            .map(|x| cascade.descendants(x))
+            .collect::<Vec<_>>(),
+        vec![14, 6, 3, 1, 0, 0]
+    );
+    assert!(cascade.table().contains("\n4\t8\t1\t1x1,2x2,3x4\n"));
+    assert!(cascade.to_dot().contains("card3 -> card4 [label=\"4\"];"));
+}
+
+#[cfg(test)]
+#[test]
+fn cascade_rules_test() {
+    let cards = TEST_INPUT.lines().map(Card::parse).collect::<Vec<_>>();
+
+    // Cards 3 and 4 win copies past the end. Card 1 gets 3 of them after it has already been
+    // processed, so they don't hand card 2 any more copies.
+    let deck = [
+        "Card 1: 5 | 5",
+        "Card 2: 5 | 6",
+        "Card 3: 5 6 | 5 6",
+        "Card 4: 5 6 | 6 5",
+    ]
+    .map(Card::parse);
+    let wrapped = Cascade::new(&deck, &WrapAround).unwrap();
+    assert_eq!(
+        (0..deck.len())
+            .map(|x| wrapped.copies(x))
+            .collect::<Vec<_>>(),
+        vec![4, 4, 1, 2]
+    );
+    assert_eq!(wrapped.sources(0), &[(2, 1), (3, 2)]);
+    assert_eq!(wrapped.sources(1), &[(0, 1), (3, 2)]);
+    assert_eq!(wrapped.total_cards(), 11);
+
+    let capped = Cascade::new(
+        &cards,
+        &Capped {
+            rule: NextCards,
+            cap: 3,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        (0..cards.len())
+            .map(|x| capped.copies(x))
+            .collect::<Vec<_>>(),
+        vec![1, 2, 3, 3, 3, 1]
+    );
+    assert_eq!(capped.sources(2), &[(0, 1), (1, 1)]);
+    // Card 4 was already full when card 3 got to it.
+    assert_eq!(capped.sources(3), &[(0, 1), (1, 1)]);
+    assert!(!capped.table().contains("x0"));
+    assert!(!capped.to_dot().contains("label=\"0\""));
+
+    let by_number = Cascade::new(&cards, &ByMatchingNumbers::new(&cards)).unwrap();
+    // Card 3 matches 1 and 21, card 4 matches 84; only card 1 exists among those ids.
+    assert_eq!(by_number.sources(0), &[(2, 1)]);
+    assert_eq!(by_number.total_cards(), 7);
+
+    let deck = (1..=200)
+        .map(|id| {
+            let numbers = (1..=100)
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Card::parse(&format!("Card {}: {} | {}", id, numbers, numbers))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        Cascade::new(&deck, &NextCards).unwrap_err(),
+        CopyOverflow { card: 130 }
+    );
 }
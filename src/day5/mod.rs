@@ -1,3 +1,6 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+
 const INPUT: &str = include_str!("input");
 const TEST_INPUT: &str = include_str!("test_input");
 
@@ -42,6 +45,22 @@ impl AlmanacRange {
             })
         }
     }
+
+    pub fn invert(&self) -> AlmanacRange {
+        AlmanacRange {
+            destination_start: self.source_start,
+            source_start: self.destination_start,
+            length: self.length,
+        }
+    }
+
+    // The part of the source that this line maps into `range`.
+    pub fn inverse_map_range(&self, range: ValueRange) -> Option<ValueRange> {
+        self.invert().map_range(range).map(|x| ValueRange {
+            start: x.destination_start,
+            length: x.length,
+        })
+    }
 }
 
 #[derive(Debug, Default)]
@@ -51,11 +70,7 @@ struct AlmanacRanges {
 impl AlmanacRanges {
     pub fn parse<'a>(input: &mut impl Iterator<Item = &'a str>) -> Self {
         let mut out = Self::default();
-        loop {
-            let line = match input.next() {
-                Some(line) => line,
-                None => break,
-            };
+        for line in input.by_ref() {
             if line.is_empty() {
                 break;
             }
@@ -65,25 +80,50 @@ impl AlmanacRanges {
     }
 
     pub fn map_value(&self, value: u64) -> u64 {
-        for range in &self.ranges {
+        self.map_value_traced(value).0
+    }
+
+    // Also returns the index of the line used, or `None` for the identity.
+    pub fn map_value_traced(&self, value: u64) -> (u64, Option<usize>) {
+        for (index, range) in self.ranges.iter().enumerate() {
             if let Some(mapped_value) = range.map_value(value) {
-                return mapped_value;
+                return (mapped_value, Some(index));
             }
         }
-        value
+        (value, None)
     }
 
-    pub fn map_range(&self, range: ValueRange) -> Vec<ValueRange> {
+    pub fn map_range(&self, range: ValueRange) -> ValueRangeSet {
+        self.map_range_pieces(range)
+            .into_iter()
+            .map(|x| ValueRange {
+                start: x.destination_start,
+                length: x.length,
+            })
+            .collect()
+    }
+
+    // Splits `range` into the pieces handled by each map line, plus identity pieces for the
+    // gaps between them, sorted by source.
+    pub fn map_range_pieces(&self, range: ValueRange) -> Vec<AlmanacRange> {
+        self.map_range_traced(range)
+            .into_iter()
+            .map(|(piece, _)| piece)
+            .collect()
+    }
+
+    // `map_range_pieces`, with the index of the line behind each piece.
+    pub fn map_range_traced(&self, range: ValueRange) -> Vec<(AlmanacRange, Option<usize>)> {
         let mut used_ranges = vec![];
-        for map_range in &self.ranges {
+        for (index, map_range) in self.ranges.iter().enumerate() {
             if let Some(mapped_range) = map_range.map_range(range) {
-                used_ranges.push(mapped_range);
+                used_ranges.push((mapped_range, Some(index)));
             }
         }
-        used_ranges.sort_by(|a, b| a.source_start.cmp(&b.source_start));
+        used_ranges.sort_by_key(|(x, _)| x.source_start);
         let mut current_index = range.start;
         let mut to_add = vec![];
-        for used_range in &used_ranges {
+        for (used_range, _) in &used_ranges {
             if current_index < used_range.source_start {
                 to_add.push(AlmanacRange {
                     source_start: current_index,
@@ -101,27 +141,149 @@ impl AlmanacRanges {
             });
         }
 
+        used_ranges.extend(to_add.into_iter().map(|x| (x, None)));
+        used_ranges.sort_by_key(|(x, _)| x.source_start);
         used_ranges
-            .into_iter()
-            .chain(to_add)
-            .map(|x| ValueRange {
-                start: x.destination_start,
-                length: x.length,
-            })
-            .collect()
+    }
+
+    // Every value that maps to `value`. More than one is possible since the map lines and the
+    // identity gaps between them can overlap in the destination.
+    pub fn inverse_map_value(&self, value: u64) -> Vec<u64> {
+        self.inverse_map_range(ValueRange {
+            start: value,
+            length: 1,
+        })
+        .iter()
+        .flat_map(|x| x.start..x.end())
+        .collect()
+    }
+
+    pub fn inverse_map_range(&self, range: ValueRange) -> ValueRangeSet {
+        let mut out = self
+            .ranges
+            .iter()
+            .filter_map(|x| x.inverse_map_range(range))
+            .collect::<Vec<_>>();
+        // Values of `range` outside every source range map to themselves.
+        out.extend(
+            self.map_range_pieces(range)
+                .into_iter()
+                .filter(|piece| {
+                    !self
+                        .ranges
+                        .iter()
+                        .any(|x| x.map_value(piece.source_start).is_some())
+                })
+                .map(|piece| ValueRange {
+                    start: piece.source_start,
+                    length: piece.length,
+                }),
+        );
+        out.into_iter().collect()
+    }
+
+    // Problems that make the result depend on line order or overflow while mapping.
+    // `first_line` is the input line number of the first range.
+    pub fn validate(&self, first_line: usize) -> Vec<RangeIssue> {
+        let mut out = vec![];
+        for (index, range) in self.ranges.iter().enumerate() {
+            let line = first_line + index;
+            if range.length == 0 {
+                out.push(RangeIssue::ZeroLength { line });
+            }
+            if range.source_start.checked_add(range.length).is_none()
+                || range.destination_start.checked_add(range.length).is_none()
+            {
+                out.push(RangeIssue::Overflow { line });
+            }
+        }
+        for (index, range) in self.ranges.iter().enumerate() {
+            for (other_index, other) in self.ranges.iter().enumerate().skip(index + 1) {
+                let end = range.source_start.saturating_add(range.length);
+                let other_end = other.source_start.saturating_add(other.length);
+                if range.source_start < other_end && other.source_start < end {
+                    out.push(RangeIssue::Overlap {
+                        line: first_line + index,
+                        other_line: first_line + other_index,
+                    });
+                }
+            }
+        }
+        out.sort_by_key(|x| x.line());
+        out
+    }
+
+    // A single map equivalent to applying `self` and then `next`. Identity pieces are left
+    // implicit, like in the input.
+    pub fn compose(&self, next: &AlmanacRanges) -> AlmanacRanges {
+        let everything = ValueRange {
+            start: 0,
+            length: u64::MAX,
+        };
+        let mut ranges = vec![];
+        for piece in self.map_range_pieces(everything) {
+            let destination = ValueRange {
+                start: piece.destination_start,
+                length: piece.length,
+            };
+            for next_piece in next.map_range_pieces(destination) {
+                let source_start = piece.source_start + next_piece.source_start - destination.start;
+                if source_start != next_piece.destination_start {
+                    ranges.push(AlmanacRange {
+                        destination_start: next_piece.destination_start,
+                        source_start,
+                        length: next_piece.length,
+                    });
+                }
+            }
+        }
+        AlmanacRanges { ranges }
+    }
+}
+impl Display for AlmanacRanges {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for range in &self.ranges {
+            writeln!(
+                f,
+                "{} {} {}",
+                range.destination_start, range.source_start, range.length
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RangeIssue {
+    // Both lines claim some of the same source values, so the earlier one wins.
+    Overlap { line: usize, other_line: usize },
+    ZeroLength { line: usize },
+    // The source or destination range runs past `u64::MAX`.
+    Overflow { line: usize },
+}
+impl RangeIssue {
+    pub fn line(&self) -> usize {
+        match self {
+            RangeIssue::Overlap { line, .. }
+            | RangeIssue::ZeroLength { line }
+            | RangeIssue::Overflow { line } => *line,
+        }
     }
 }
 
+#[derive(Debug)]
+struct AlmanacMap {
+    source: String,
+    destination: String,
+    ranges: AlmanacRanges,
+    // Input line number of the header.
+    line: usize,
+}
+
 #[derive(Debug)]
 struct Almanac {
     seeds: Vec<u64>,
-    seed_to_soil: AlmanacRanges,
-    soil_to_fertilizer: AlmanacRanges,
-    fertilizer_to_water: AlmanacRanges,
-    water_to_light: AlmanacRanges,
-    light_to_temperature: AlmanacRanges,
-    temperature_to_humidity: AlmanacRanges,
-    humidity_to_location: AlmanacRanges,
+    maps: Vec<AlmanacMap>,
 }
 impl Almanac {
     pub fn parse(input: &str) -> Self {
@@ -131,79 +293,752 @@ impl Almanac {
             .unwrap()
             .split_at("seeds: ".len())
             .1
-            .trim()
-            .split(' ')
+            .split_whitespace()
             .map(|x| x.parse().unwrap())
             .collect::<Vec<_>>();
         assert_eq!(lines.next(), Some(""));
-        assert_eq!(lines.next(), Some("seed-to-soil map:"));
-        let seed_to_soil = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("soil-to-fertilizer map:"));
-        let soil_to_fertilizer = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("fertilizer-to-water map:"));
-        let fertilizer_to_water = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("water-to-light map:"));
-        let water_to_light = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("light-to-temperature map:"));
-        let light_to_temperature = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("temperature-to-humidity map:"));
-        let temperature_to_humidity = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), Some("humidity-to-location map:"));
-        let humidity_to_location = AlmanacRanges::parse(&mut lines);
-        assert_eq!(lines.next(), None);
 
-        Self {
-            seeds,
-            seed_to_soil,
-            soil_to_fertilizer,
-            fertilizer_to_water,
-            water_to_light,
-            light_to_temperature,
-            temperature_to_humidity,
-            humidity_to_location,
+        let mut maps = vec![];
+        let mut line = 3;
+        while let Some(header) = lines.next() {
+            let (source, destination) = header
+                .strip_suffix(" map:")
+                .and_then(|x| x.split_once("-to-"))
+                .unwrap_or_else(|| panic!("Invalid map header: {}", header));
+            let ranges = AlmanacRanges::parse(&mut lines);
+            let length = ranges.ranges.len();
+            maps.push(AlmanacMap {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                ranges,
+                line,
+            });
+            line += length + 2;
+        }
+
+        Self { seeds, maps }
+    }
+
+    // Like `parse`, but refuses almanacs where `validate` finds anything.
+    pub fn parse_strict(input: &str) -> Result<Self, Vec<RangeIssue>> {
+        let almanac = Self::parse(input);
+        let issues = almanac.validate();
+        if issues.is_empty() {
+            Ok(almanac)
+        } else {
+            Err(issues)
+        }
+    }
+
+    pub fn validate(&self) -> Vec<RangeIssue> {
+        self.maps
+            .iter()
+            .flat_map(|x| x.ranges.validate(x.line + 1))
+            .collect()
+    }
+
+    // The part 2 reading of the seeds line: pairs of start and length.
+    pub fn seed_ranges(&self) -> ValueRangeSet {
+        self.seeds
+            .chunks(2)
+            .map(|x| ValueRange {
+                start: x[0],
+                length: x[1],
+            })
+            .collect()
+    }
+
+    pub fn map(&self, source: &str, destination: &str) -> Option<&AlmanacMap> {
+        self.maps
+            .iter()
+            .find(|x| x.source == source && x.destination == destination)
+    }
+
+    // Shortest chain of maps leading from `source` to `destination`, found breadth first.
+    pub fn path(&self, source: &str, destination: &str) -> Option<Vec<&AlmanacMap>> {
+        let mut previous: HashMap<&str, Option<&AlmanacMap>> = HashMap::from([(source, None)]);
+        let mut queue = VecDeque::from([source]);
+        while let Some(category) = queue.pop_front() {
+            if category == destination {
+                let mut out = vec![];
+                let mut current = category;
+                while let Some(Some(map)) = previous.get(current) {
+                    out.push(*map);
+                    current = &map.source;
+                }
+                out.reverse();
+                return Some(out);
+            }
+            for map in self.maps.iter().filter(|x| x.source == category) {
+                if !previous.contains_key(map.destination.as_str()) {
+                    previous.insert(&map.destination, Some(map));
+                    queue.push_back(&map.destination);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn compose_path(&self, source: &str, destination: &str) -> Option<AlmanacRanges> {
+        Some(
+            self.path(source, destination)?
+                .into_iter()
+                .fold(AlmanacRanges::default(), |composed, map| {
+                    composed.compose(&map.ranges)
+                }),
+        )
+    }
+
+    // Every range of `source` values that ends up inside `range` of `destination` values.
+    pub fn inverse_map_range(
+        &self,
+        source: &str,
+        destination: &str,
+        range: ValueRange,
+    ) -> Option<ValueRangeSet> {
+        Some(Self::inverse_map_range_along(
+            &self.path(source, destination)?,
+            range,
+        ))
+    }
+
+    fn inverse_map_range_along(path: &[&AlmanacMap], range: ValueRange) -> ValueRangeSet {
+        path.iter()
+            .rev()
+            .fold(ValueRangeSet::from(range), |ranges, map| {
+                ranges
+                    .iter()
+                    .map(|x| map.ranges.inverse_map_range(*x))
+                    .fold(ValueRangeSet::default(), |x, y| x.union(&y))
+            })
+    }
+
+    // Part 2 solved backwards: the answer is the smallest `destination` value `x` where some
+    // of `sources` lead to `0..=x`. That only grows with `x`, so it can be bisected.
+    pub fn lowest_destination_by_inverse(
+        &self,
+        source: &str,
+        destination: &str,
+        sources: &ValueRangeSet,
+    ) -> Option<u64> {
+        let path = self.path(source, destination)?;
+        let reaches = |value: u64| {
+            let range = ValueRange {
+                start: 0,
+                length: value + 1,
+            };
+            !Self::inverse_map_range_along(&path, range)
+                .intersection(sources)
+                .is_empty()
+        };
+        if !reaches(u64::MAX - 1) {
+            return None;
         }
+        let (mut low, mut high) = (0, u64::MAX - 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if reaches(middle) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Some(low)
+    }
+
+    // Every category `value` passes through on its way to `destination`, and the line used at
+    // each step.
+    pub fn trace_value(&self, source: &str, destination: &str, value: u64) -> Option<ValueTrace> {
+        let mut steps = vec![];
+        let mut current = value;
+        for map in self.path(source, destination)? {
+            let (mapped, index) = map.ranges.map_value_traced(current);
+            steps.push(ValueStep {
+                category: map.destination.clone(),
+                value: mapped,
+                rule: MapRule::new(map, index),
+            });
+            current = mapped;
+        }
+        Some(ValueTrace {
+            category: source.to_string(),
+            value,
+            steps,
+        })
+    }
+
+    // Like `trace_value`, but for a whole range, showing where each map splits it.
+    pub fn trace_range(
+        &self,
+        source: &str,
+        destination: &str,
+        range: ValueRange,
+    ) -> Option<RangeTrace> {
+        let mut steps = vec![];
+        let mut current = ValueRangeSet::from(range);
+        for map in self.path(source, destination)? {
+            let pieces = current
+                .iter()
+                .flat_map(|x| map.ranges.map_range_traced(*x))
+                .map(|(piece, index)| RangePiece {
+                    source: ValueRange {
+                        start: piece.source_start,
+                        length: piece.length,
+                    },
+                    destination: ValueRange {
+                        start: piece.destination_start,
+                        length: piece.length,
+                    },
+                    rule: MapRule::new(map, index),
+                })
+                .collect::<Vec<_>>();
+            current = pieces.iter().map(|x| x.destination).collect();
+            steps.push(RangeStep {
+                category: map.destination.clone(),
+                pieces,
+            });
+        }
+        Some(RangeTrace {
+            category: source.to_string(),
+            range,
+            steps,
+        })
+    }
+
+    pub fn map_value(&self, source: &str, destination: &str, value: u64) -> Option<u64> {
+        Some(
+            self.path(source, destination)?
+                .into_iter()
+                .fold(value, |value, map| map.ranges.map_value(value)),
+        )
+    }
+
+    pub fn map_range(
+        &self,
+        source: &str,
+        destination: &str,
+        range: ValueRange,
+    ) -> Option<ValueRangeSet> {
+        Some(self.path(source, destination)?.into_iter().fold(
+            ValueRangeSet::from(range),
+            |ranges, map| {
+                ranges
+                    .iter()
+                    .map(|x| map.ranges.map_range(*x))
+                    .fold(ValueRangeSet::default(), |x, y| x.union(&y))
+            },
+        ))
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+enum MapRule {
+    // Input line number of the map line used.
+    Line(usize),
+    Identity,
+}
+impl MapRule {
+    fn new(map: &AlmanacMap, index: Option<usize>) -> Self {
+        match index {
+            Some(index) => MapRule::Line(map.line + 1 + index),
+            None => MapRule::Identity,
+        }
+    }
+}
+impl Display for MapRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapRule::Line(line) => write!(f, "line {}", line),
+            MapRule::Identity => write!(f, "identity"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct ValueStep {
+    category: String,
+    value: u64,
+    rule: MapRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct ValueTrace {
+    category: String,
+    value: u64,
+    steps: Vec<ValueStep>,
+}
+impl ValueTrace {
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+// seed 79
+// soil 81 (line 5)
+impl Display for ValueTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.category, self.value)?;
+        for step in &self.steps {
+            writeln!(f, "{} {} ({})", step.category, step.value, step.rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct RangePiece {
+    source: ValueRange,
+    destination: ValueRange,
+    rule: MapRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct RangeStep {
+    category: String,
+    pieces: Vec<RangePiece>,
+}
+impl RangeStep {
+    // Values where this step cut an incoming range in two.
+    pub fn split_points(&self) -> Vec<u64> {
+        self.pieces
+            .windows(2)
+            .filter(|x| x[0].source.end() == x[1].source.start)
+            .map(|x| x[1].source.start)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct RangeTrace {
+    category: String,
+    range: ValueRange,
+    steps: Vec<RangeStep>,
+}
+impl RangeTrace {
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+// seed 79..93
+// soil
+//   79..93 -> 81..95 (line 5)
+impl Display for RangeTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.category, self.range)?;
+        for step in &self.steps {
+            write!(f, "{}", step.category)?;
+            if !step.split_points().is_empty() {
+                let points = step
+                    .split_points()
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, " (split at {})", points.join(", "))?;
+            }
+            writeln!(f)?;
+            for piece in &step.pieces {
+                writeln!(
+                    f,
+                    "  {} -> {} ({})",
+                    piece.source, piece.destination, piece.rule
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 struct ValueRange {
     start: u64,
     length: u64,
 }
+impl ValueRange {
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+
+    pub fn overlaps(&self, other: &ValueRange) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+}
+
+impl Display for ValueRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end())
+    }
+}
+
+// Sorted, non-empty ranges where no two overlap or touch.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ValueRangeSet {
+    ranges: Vec<ValueRange>,
+}
+impl ValueRangeSet {
+    pub fn iter(&self) -> impl Iterator<Item = &ValueRange> + '_ {
+        self.ranges.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.ranges.first().map(|x| x.start)
+    }
+
+    // Number of values in the set.
+    pub fn size(&self) -> u64 {
+        self.ranges.iter().map(|x| x.length).sum()
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        let index = self.ranges.partition_point(|x| x.end() <= value);
+        self.ranges.get(index).is_some_and(|x| x.start <= value)
+    }
+
+    pub fn union(&self, other: &ValueRangeSet) -> ValueRangeSet {
+        self.ranges.iter().chain(&other.ranges).copied().collect()
+    }
+
+    pub fn intersection(&self, other: &ValueRangeSet) -> ValueRangeSet {
+        let mut out = vec![];
+        let (mut left, mut right) = (
+            self.ranges.iter().peekable(),
+            other.ranges.iter().peekable(),
+        );
+        while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+            let start = a.start.max(b.start);
+            let end = a.end().min(b.end());
+            if start < end {
+                out.push(ValueRange {
+                    start,
+                    length: end - start,
+                });
+            }
+            if a.end() < b.end() {
+                left.next();
+            } else {
+                right.next();
+            }
+        }
+        ValueRangeSet { ranges: out }
+    }
+
+    pub fn difference(&self, other: &ValueRangeSet) -> ValueRangeSet {
+        let mut out = vec![];
+        let mut others = other.ranges.iter().peekable();
+        for range in &self.ranges {
+            let mut start = range.start;
+            while let Some(next) = others.peek() {
+                if next.end() <= start {
+                    others.next();
+                    continue;
+                }
+                if next.start >= range.end() {
+                    break;
+                }
+                if next.start > start {
+                    out.push(ValueRange {
+                        start,
+                        length: next.start - start,
+                    });
+                }
+                start = next.end();
+                if start >= range.end() {
+                    break;
+                }
+                others.next();
+            }
+            if start < range.end() {
+                out.push(ValueRange {
+                    start,
+                    length: range.end() - start,
+                });
+            }
+        }
+        ValueRangeSet { ranges: out }
+    }
+
+    // Everything in `bound` that isn't in the set.
+    pub fn complement(&self, bound: ValueRange) -> ValueRangeSet {
+        ValueRangeSet::from(bound).difference(self)
+    }
+}
+impl From<ValueRange> for ValueRangeSet {
+    fn from(value: ValueRange) -> Self {
+        [value].into_iter().collect()
+    }
+}
+impl FromIterator<ValueRange> for ValueRangeSet {
+    fn from_iter<T: IntoIterator<Item = ValueRange>>(iter: T) -> Self {
+        let mut ranges = iter
+            .into_iter()
+            .filter(|x| x.length > 0)
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|x| x.start);
+        let mut out: Vec<ValueRange> = vec![];
+        for range in ranges {
+            match out.last_mut() {
+                Some(last) if range.start <= last.end() => {
+                    last.length = last.end().max(range.end()) - last.start;
+                }
+                _ => out.push(range),
+            }
+        }
+        Self { ranges: out }
+    }
+}
 
 pub fn day5() {
     let almanac = Almanac::parse(INPUT);
     let location_numbers = almanac
         .seeds
         .iter()
-        .copied()
-        .map(|x| almanac.seed_to_soil.map_value(x))
-        .map(|x| almanac.soil_to_fertilizer.map_value(x))
-        .map(|x| almanac.fertilizer_to_water.map_value(x))
-        .map(|x| almanac.water_to_light.map_value(x))
-        .map(|x| almanac.light_to_temperature.map_value(x))
-        .map(|x| almanac.temperature_to_humidity.map_value(x))
-        .map(|x| almanac.humidity_to_location.map_value(x))
+        .map(|x| almanac.map_value("seed", "location", *x).unwrap())
         .collect::<Vec<_>>();
     println!("Day 5 part 1: {:?}", location_numbers.iter().min().unwrap());
 
+    let seed_to_location = almanac.compose_path("seed", "location").unwrap();
     let location_numbers = almanac
+        .seed_ranges()
+        .iter()
+        .map(|x| seed_to_location.map_range(*x))
+        .fold(ValueRangeSet::default(), |x, y| x.union(&y));
+    println!("Day 5 part 2: {:?}", location_numbers.min().unwrap());
+}
+
+#[cfg(test)]
+#[test]
+fn path_test() {
+    let almanac = Almanac::parse(TEST_INPUT);
+    let path = almanac
+        .path("light", "location")
+        .unwrap()
+        .into_iter()
+        .map(|x| x.destination.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(path, vec!["temperature", "humidity", "location"]);
+    assert!(almanac.path("location", "seed").is_none());
+    assert_eq!(almanac.path("soil", "soil").unwrap().len(), 0);
+
+    let locations = almanac
         .seeds
-        .chunks(2)
-        .map(|x| ValueRange {
-            start: x[0],
-            length: x[1],
-        })
-        .flat_map(|x| almanac.seed_to_soil.map_range(x))
-        .flat_map(|x| almanac.soil_to_fertilizer.map_range(x))
-        .flat_map(|x| almanac.fertilizer_to_water.map_range(x))
-        .flat_map(|x| almanac.water_to_light.map_range(x))
-        .flat_map(|x| almanac.light_to_temperature.map_range(x))
-        .flat_map(|x| almanac.temperature_to_humidity.map_range(x))
-        .flat_map(|x| almanac.humidity_to_location.map_range(x))
+        .iter()
+        .map(|x| almanac.map_value("seed", "location", *x).unwrap())
         .collect::<Vec<_>>();
-    println!(
-        "Day 5 part 2: {:?}",
-        location_numbers.iter().map(|x| x.start).min().unwrap()
+    assert_eq!(locations, vec![82, 43, 86, 35]);
+    assert_eq!(almanac.map_value("seed", "water", 79), Some(81));
+    let lowest = almanac
+        .map_range(
+            "seed",
+            "location",
+            ValueRange {
+                start: 82,
+                length: 1,
+            },
+        )
+        .unwrap();
+    assert_eq!(lowest.min(), Some(46));
+}
+
+#[cfg(test)]
+#[test]
+fn compose_test() {
+    for input in [TEST_INPUT, INPUT] {
+        let almanac = Almanac::parse(input);
+        let composed = almanac.compose_path("seed", "location").unwrap();
+        let reparsed = AlmanacRanges::parse(&mut composed.to_string().lines());
+        for seed in almanac.seeds.iter().copied().chain(0..200) {
+            let expected = almanac.map_value("seed", "location", seed).unwrap();
+            assert_eq!(composed.map_value(seed), expected);
+            assert_eq!(reparsed.map_value(seed), expected);
+        }
+        for range in almanac.seeds.chunks(2) {
+            let range = ValueRange {
+                start: range[0],
+                length: range[1],
+            };
+            let stepwise = almanac.map_range("seed", "location", range).unwrap();
+            let composed = composed.map_range(range);
+            assert_eq!(stepwise, composed);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn inverse_test() {
+    let almanac = Almanac::parse(TEST_INPUT);
+    let seed_to_soil = &almanac.map("seed", "soil").unwrap().ranges;
+    assert_eq!(seed_to_soil.inverse_map_value(51), vec![99]);
+    assert_eq!(seed_to_soil.inverse_map_value(81), vec![79]);
+    assert_eq!(seed_to_soil.inverse_map_value(49), vec![49]);
+    let overlapping = AlmanacRanges::parse(&mut ["10 0 5"].into_iter());
+    assert_eq!(overlapping.inverse_map_value(12), vec![2, 12]);
+    assert_eq!(overlapping.inverse_map_value(3), Vec::<u64>::new());
+
+    for location in 0..120 {
+        let seeds = almanac
+            .inverse_map_range(
+                "seed",
+                "location",
+                ValueRange {
+                    start: location,
+                    length: 1,
+                },
+            )
+            .unwrap();
+        for seed in seeds.iter().flat_map(|x| x.start..x.end()) {
+            assert_eq!(almanac.map_value("seed", "location", seed), Some(location));
+        }
+    }
+
+    for input in [TEST_INPUT, INPUT] {
+        let almanac = Almanac::parse(input);
+        let seeds = almanac.seed_ranges();
+        let forward = seeds
+            .iter()
+            .filter_map(|x| almanac.map_range("seed", "location", *x).unwrap().min())
+            .min();
+        assert_eq!(
+            almanac.lowest_destination_by_inverse("seed", "location", &seeds),
+            forward
+        );
+        assert_eq!(
+            almanac.lowest_destination_by_inverse("location", "seed", &seeds),
+            None
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn value_range_set_test() {
+    fn set(ranges: &[(u64, u64)]) -> ValueRangeSet {
+        ranges
+            .iter()
+            .map(|(start, end)| ValueRange {
+                start: *start,
+                length: end - start,
+            })
+            .collect()
+    }
+
+    let a = set(&[(10, 20), (0, 5), (5, 8), (15, 25), (30, 30)]);
+    assert_eq!(a, set(&[(0, 8), (10, 25)]));
+    assert_eq!(a.size(), 23);
+    assert!(a.contains(7) && !a.contains(8) && a.contains(24) && !a.contains(25));
+
+    let b = set(&[(3, 12), (20, 40)]);
+    assert_eq!(a.union(&b), set(&[(0, 40)]));
+    assert_eq!(a.intersection(&b), set(&[(3, 8), (10, 12), (20, 25)]));
+    assert_eq!(a.difference(&b), set(&[(0, 3), (12, 20)]));
+    assert_eq!(b.difference(&a), set(&[(8, 10), (25, 40)]));
+    assert_eq!(
+        a.complement(ValueRange {
+            start: 5,
+            length: 25
+        }),
+        set(&[(8, 10), (25, 30)])
+    );
+
+    let almanac = Almanac::parse(TEST_INPUT);
+    let soil = almanac
+        .map("seed", "soil")
+        .unwrap()
+        .ranges
+        .map_range(ValueRange {
+            start: 40,
+            length: 70,
+        });
+    // 50..98 moves to 52..100 and 98..100 to 50..52, so the pieces touch and merge back.
+    assert_eq!(soil, set(&[(40, 110)]));
+}
+
+#[cfg(test)]
+#[test]
+fn validate_test() {
+    assert!(Almanac::parse_strict(TEST_INPUT).is_ok());
+    assert!(Almanac::parse_strict(INPUT).is_ok());
+
+    let input = "seeds: 1 2
+
+a-to-b map:
+0 10 5
+20 0 0
+30 12 4
+
+b-to-c map:
+0 18446744073709551610 10
+";
+    assert_eq!(
+        Almanac::parse_strict(input).unwrap_err(),
+        vec![
+            RangeIssue::Overlap {
+                line: 4,
+                other_line: 6
+            },
+            RangeIssue::ZeroLength { line: 5 },
+            RangeIssue::Overflow { line: 9 },
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn trace_test() {
+    let almanac = Almanac::parse(TEST_INPUT);
+    let trace = almanac.trace_value("seed", "location", 79).unwrap();
+    assert_eq!(trace.steps.last().unwrap().value, 82);
+    assert_eq!(
+        trace.to_string().lines().take(3).collect::<Vec<_>>(),
+        vec!["seed 79", "soil 81 (line 5)", "fertilizer 81 (identity)"]
+    );
+
+    let trace = almanac
+        .trace_range(
+            "seed",
+            "soil",
+            ValueRange {
+                start: 45,
+                length: 10,
+            },
+        )
+        .unwrap();
+    assert_eq!(trace.steps[0].split_points(), vec![50]);
+    assert_eq!(
+        trace.to_string(),
+        "seed 45..55\nsoil (split at 50)\n  45..50 -> 45..50 (identity)\n  50..55 -> 52..57 (line 5)\n"
     );
+
+    let seeds = almanac.seed_ranges();
+    for range in seeds.iter() {
+        let trace = almanac.trace_range("seed", "location", *range).unwrap();
+        let traced = trace
+            .steps
+            .last()
+            .unwrap()
+            .pieces
+            .iter()
+            .map(|x| x.destination)
+            .collect::<ValueRangeSet>();
+        assert_eq!(Some(traced), almanac.map_range("seed", "location", *range));
+    }
+
+    #[cfg(feature = "serde")]
+    assert!(almanac
+        .trace_value("seed", "soil", 98)
+        .unwrap()
+        .to_json()
+        .unwrap()
+        .contains(r#"{"category":"soil","value":50,"rule":{"Line":4}}"#));
 }
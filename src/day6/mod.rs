@@ -1,3 +1,5 @@
+use std::ops::RangeInclusive;
+
 const INPUT: &str = include_str!("input");
 const TEST_INPUT: &str = include_str!("test_input");
 
@@ -8,92 +10,238 @@ pub struct Race {
 }
 impl Race {
     pub fn parse(input: &str) -> Vec<Self> {
-        let mut lines = input.lines();
-        let mut times = lines
-            .next()
-            .unwrap()
-            .split_at("Time:".len())
-            .1
-            .trim()
-            .to_string();
-        while times.contains("  ") {
-            times = times.replace("  ", " ");
-        }
-        let times = times.split(' ').map(|x| x.trim().parse::<u64>().unwrap());
-        let mut distances = lines
-            .next()
+        RaceSheet::parse(input, Kerning::Spaced)
+            .and_then(|x| x.races())
             .unwrap()
-            .split_at("Distance:".len())
-            .1
-            .trim()
-            .to_string();
-        while distances.contains("  ") {
-            distances = distances.replace("  ", " ");
-        }
-        let distances = distances
-            .split(' ')
-            .map(|x| x.trim().parse::<u64>().unwrap());
-        let mut out = vec![];
-        for (time, distance) in times.zip(distances) {
-            out.push(Self {
-                time,
-                distance_record: distance,
-            });
-        }
-        out
     }
 
     pub fn parse_no_split(input: &str) -> Vec<Self> {
-        let mut lines = input.lines();
-        let mut times = lines
-            .next()
+        RaceSheet::parse(input, Kerning::Joined)
+            .and_then(|x| x.races())
             .unwrap()
-            .split_at("Time:".len())
-            .1
-            .trim()
-            .to_string();
-        while times.contains(" ") {
-            times = times.replace(" ", "");
+    }
+
+    pub fn shortest_winning_hold(&self) -> Option<u64> {
+        shortest_winning_hold(self.time, self.distance_record)
+    }
+
+    pub fn winning_holds(&self, physics: &impl BoatPhysics) -> Option<RangeInclusive<u64>> {
+        physics.winning_holds(self.time, self.distance_record)
+    }
+
+    pub fn winning_count_with(&self, physics: &impl BoatPhysics) -> u64 {
+        self.winning_holds(physics)
+            .map_or(0, |x| x.end() - x.start() + 1)
+    }
+
+    pub fn winning_count(&self) -> u64 {
+        self.winning_count_with(&Linear)
+    }
+}
+
+// How far a boat gets in a race of `time` ms after holding the button for `hold` ms.
+pub trait BoatPhysics {
+    fn distance(&self, hold: u64, time: u64) -> u128;
+
+    // Holds that beat `record`. The default searches, assuming the distance rises and then
+    // falls as the hold gets longer, with any flat stretch only at the top.
+    fn winning_holds(&self, time: u64, record: u64) -> Option<RangeInclusive<u64>> {
+        let distance = |hold: u64| self.distance(hold, time);
+        let beats = |hold: u64| distance(hold) > record as u128;
+        let peak = partition_point(0, time, |x| distance(x) < distance(x + 1));
+        if !beats(peak) {
+            return None;
         }
-        let times = times.split(' ').map(|x| x.trim().parse::<u64>().unwrap());
-        let mut distances = lines
-            .next()
-            .unwrap()
-            .split_at("Distance:".len())
-            .1
-            .trim()
-            .to_string();
-        while distances.contains(" ") {
-            distances = distances.replace(" ", "");
+        let first = partition_point(0, peak, |x| !beats(x));
+        let last = partition_point(peak, time, |x| beats(x + 1));
+        Some(first..=last)
+    }
+}
+
+// First value in `low..=high` where `pred` is false, or `high` if there isn't one.
+fn partition_point(mut low: u64, mut high: u64, pred: impl Fn(u64) -> bool) -> u64 {
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if pred(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
         }
-        let distances = distances
-            .split(' ')
-            .map(|x| x.trim().parse::<u64>().unwrap());
-        let mut out = vec![];
-        for (time, distance) in times.zip(distances) {
-            out.push(Self {
-                time,
-                distance_record: distance,
-            });
+    }
+    low
+}
+
+// The puzzle's rule: each ms of holding adds 1 mm/ms of speed.
+pub struct Linear;
+impl BoatPhysics for Linear {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        hold.min(time) as u128 * (time - hold.min(time)) as u128
+    }
+
+    fn winning_holds(&self, time: u64, record: u64) -> Option<RangeInclusive<u64>> {
+        shortest_winning_hold(time, record).map(|hold| hold..=time - hold)
+    }
+}
+
+// Shortest hold that beats `record` under `Linear`, if any. Winning holds are symmetric about
+// time / 2, so the longest is `time - hold`.
+fn shortest_winning_hold(time: u64, record: u64) -> Option<u64> {
+    let (time, record) = (time as u128, record as u128);
+    let beats = |hold: u128| hold * (time - hold) > record;
+    let discriminant = (time * time).checked_sub(4 * record)?;
+    // The integer square root only gets us within one of the real root.
+    let mut hold = (time - discriminant.isqrt()) / 2;
+    while hold > 0 && beats(hold - 1) {
+        hold -= 1;
+    }
+    while hold <= time / 2 && !beats(hold) {
+        hold += 1;
+    }
+    (hold <= time / 2).then_some(hold as u64)
+}
+
+// Speed grows with the square of the hold.
+pub struct Quadratic;
+impl BoatPhysics for Quadratic {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        let hold = hold.min(time) as u128;
+        hold.saturating_mul(hold)
+            .saturating_mul(time as u128 - hold)
+    }
+}
+
+// Speed stops growing at `max_speed`.
+pub struct Capped {
+    pub max_speed: u64,
+}
+impl BoatPhysics for Capped {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        let hold = hold.min(time);
+        hold.min(self.max_speed) as u128 * (time - hold) as u128
+    }
+}
+
+// The boat sits still for `penalty` ms after the button is released.
+pub struct ChargePenalty {
+    pub penalty: u64,
+}
+impl BoatPhysics for ChargePenalty {
+    fn distance(&self, hold: u64, time: u64) -> u128 {
+        Linear.distance(hold, time.saturating_sub(self.penalty))
+    }
+
+    // Just the linear race with less time on the clock.
+    fn winning_holds(&self, time: u64, record: u64) -> Option<RangeInclusive<u64>> {
+        Linear.winning_holds(time.saturating_sub(self.penalty), record)
+    }
+}
+
+// Whether the spaces between digits separate numbers or are just bad kerning.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kerning {
+    Spaced,
+    Joined,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RaceParseError {
+    MissingLabel {
+        line: usize,
+    },
+    InvalidNumber {
+        line: usize,
+        value: String,
+    },
+    MissingRow {
+        label: String,
+    },
+    // Lines are 1-based; `expected` comes from the first row.
+    CountMismatch {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+// Labelled rows of numbers, one column per race.
+// Time:      7  15   30
+// Distance:  9  40  200
+#[derive(Clone, Debug, Default)]
+pub struct RaceSheet {
+    rows: Vec<(String, Vec<u64>)>,
+}
+impl RaceSheet {
+    pub fn parse(input: &str, kerning: Kerning) -> Result<Self, RaceParseError> {
+        let mut out = Self::default();
+        for (index, text) in input.lines().enumerate() {
+            let line = index + 1;
+            if text.trim().is_empty() {
+                continue;
+            }
+            let (label, numbers) = text
+                .split_once(':')
+                .ok_or(RaceParseError::MissingLabel { line })?;
+            let numbers = match kerning {
+                Kerning::Spaced => numbers.split_whitespace().map(str::to_string).collect(),
+                Kerning::Joined => {
+                    let joined = numbers.split_whitespace().collect::<String>();
+                    if joined.is_empty() {
+                        vec![]
+                    } else {
+                        vec![joined]
+                    }
+                }
+            };
+            let values = numbers
+                .into_iter()
+                .map(|x| {
+                    x.parse()
+                        .map_err(|_| RaceParseError::InvalidNumber { line, value: x })
+                })
+                .collect::<Result<Vec<u64>, _>>()?;
+            if let Some((_, first)) = out.rows.first() {
+                if first.len() != values.len() {
+                    return Err(RaceParseError::CountMismatch {
+                        line,
+                        expected: first.len(),
+                        found: values.len(),
+                    });
+                }
+            }
+            out.rows.push((label.trim().to_string(), values));
         }
-        out
+        Ok(out)
     }
 
-    pub fn solve_race_for_distance(&self, distance: u64) -> [f64; 2] {
-        let sqrt = ((self.time as f64).powi(2) - 4.0 * distance as f64).sqrt();
-        [
-            0.5 * (self.time as f64 - sqrt),
-            0.5 * (self.time as f64 + sqrt),
-        ]
+    pub fn labels(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rows.iter().map(|(label, _)| label.as_str())
     }
 
-    pub fn solve_race(&self) -> [f64; 2] {
-        self.solve_race_for_distance(self.distance_record)
+    pub fn row(&self, label: &str) -> Option<&[u64]> {
+        self.rows
+            .iter()
+            .find(|(x, _)| x == label)
+            .map(|(_, values)| values.as_slice())
     }
 
-    pub fn winning_count(&self) -> u64 {
-        let [min, max] = self.solve_race();
-        ((max - 0.01).floor() as u64).min(self.time) - ((min + 0.01).floor() as u64).max(0)
+    fn required_row(&self, label: &str) -> Result<&[u64], RaceParseError> {
+        self.row(label).ok_or_else(|| RaceParseError::MissingRow {
+            label: label.to_string(),
+        })
+    }
+
+    // Races from the `Time` and `Distance` rows; any other rows are left for the caller.
+    pub fn races(&self) -> Result<Vec<Race>, RaceParseError> {
+        let times = self.required_row("Time")?;
+        let distances = self.required_row("Distance")?;
+        Ok(times
+            .iter()
+            .zip(distances)
+            .map(|(time, distance)| Race {
+                time: *time,
+                distance_record: *distance,
+            })
+            .collect())
     }
 }
 
@@ -106,3 +254,125 @@ pub fn day6() {
     let win_counts = races.iter().map(Race::winning_count).collect::<Vec<_>>();
     println!("Day 6 part 2: {}", win_counts.iter().product::<u64>());
 }
+
+#[cfg(test)]
+#[test]
+fn winning_count_test() {
+    let counts = Race::parse(TEST_INPUT)
+        .iter()
+        .map(Race::winning_count)
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![4, 8, 9]);
+    assert_eq!(Race::parse_no_split(TEST_INPUT)[0].winning_count(), 71503);
+
+    for time in 0..40 {
+        for distance_record in 0..420 {
+            let race = Race {
+                time,
+                distance_record,
+            };
+            let brute_force = (0..=time)
+                .filter(|hold| hold * (time - hold) > distance_record)
+                .count() as u64;
+            assert_eq!(race.winning_count(), brute_force, "{:?}", race);
+        }
+    }
+
+    // Records set by an exact hold, where time squared is far past f64's precision.
+    for (time, hold) in [
+        (u64::MAX, 1),
+        ((1 << 32) + 3, (1 << 31) - 1),
+        (1 << 40, 1 << 20),
+    ] {
+        let race = Race {
+            time,
+            distance_record: hold * (time - hold),
+        };
+        assert_eq!(race.winning_count(), time - 2 * hold - 1);
+        assert_eq!(race.shortest_winning_hold(), Some(hold + 1));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn race_sheet_test() {
+    let sheet = RaceSheet::parse(
+        "Time:      7  15   30\nDistance:  9  40  200\nCharge:    1   0    2\n",
+        Kerning::Spaced,
+    )
+    .unwrap();
+    assert_eq!(
+        sheet.labels().collect::<Vec<_>>(),
+        vec!["Time", "Distance", "Charge"]
+    );
+    assert_eq!(sheet.row("Charge"), Some([1, 0, 2].as_slice()));
+    assert_eq!(sheet.races().unwrap().len(), 3);
+
+    let sheet = RaceSheet::parse(TEST_INPUT, Kerning::Joined).unwrap();
+    assert_eq!(sheet.row("Distance"), Some([940200].as_slice()));
+
+    assert_eq!(
+        RaceSheet::parse("Time: 7 15\nDistance: 9 40 200", Kerning::Spaced).unwrap_err(),
+        RaceParseError::CountMismatch {
+            line: 2,
+            expected: 2,
+            found: 3
+        }
+    );
+    assert_eq!(
+        RaceSheet::parse("Time: 7\n9", Kerning::Spaced).unwrap_err(),
+        RaceParseError::MissingLabel { line: 2 }
+    );
+    assert_eq!(
+        RaceSheet::parse("Time: 7 x", Kerning::Spaced).unwrap_err(),
+        RaceParseError::InvalidNumber {
+            line: 1,
+            value: "x".to_string()
+        }
+    );
+    assert_eq!(
+        RaceSheet::parse("Time: 7", Kerning::Spaced)
+            .unwrap()
+            .races()
+            .unwrap_err(),
+        RaceParseError::MissingRow {
+            label: "Distance".to_string()
+        }
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn physics_test() {
+    fn check(physics: &impl BoatPhysics) {
+        for time in 0..30 {
+            for record in 0..600 {
+                let winners = (0..=time)
+                    .filter(|hold| physics.distance(*hold, time) > record as u128)
+                    .collect::<Vec<_>>();
+                let expected = winners
+                    .first()
+                    .zip(winners.last())
+                    .map(|(first, last)| *first..=*last);
+                assert_eq!(physics.winning_holds(time, record), expected);
+                assert_eq!(
+                    winners.len() as u64,
+                    expected.map_or(0, |x| x.count() as u64)
+                );
+            }
+        }
+    }
+    check(&Linear);
+    check(&Quadratic);
+    for max_speed in [0, 1, 5, 12, 40] {
+        check(&Capped { max_speed });
+    }
+    for penalty in [0, 3, 50] {
+        check(&ChargePenalty { penalty });
+    }
+
+    let race = Race::parse(TEST_INPUT)[0];
+    assert_eq!(race.winning_count_with(&ChargePenalty { penalty: 0 }), 4);
+    assert_eq!(race.winning_count_with(&Capped { max_speed: 2 }), 1);
+    assert_eq!(race.winning_count_with(&Quadratic), 5);
+}
@@ -0,0 +1,132 @@
+use grid::Grid;
+
+// Offsets are (row, col).
+pub const ORTHOGONAL_OFFSETS: [(isize, isize); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+pub const ALL_OFFSETS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Pos {
+    pub row: usize,
+    pub col: usize,
+}
+impl Pos {
+    pub const fn new(row: usize, col: usize) -> Self {
+        Self { row, col }
+    }
+
+    pub fn offset(self, row: isize, col: isize) -> Option<Self> {
+        Some(Self {
+            row: self.row.checked_add_signed(row)?,
+            col: self.col.checked_add_signed(col)?,
+        })
+    }
+}
+
+// Wraps `grid::Grid` so every access goes through a `Pos` and rows/cols can't be swapped.
+#[derive(Debug, Clone)]
+pub struct Grid2d<T> {
+    grid: Grid<T>,
+}
+impl<T: Default> Grid2d<T> {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            grid: Grid::new(rows, cols),
+        }
+    }
+
+    pub fn insert_row(&mut self, row: usize) {
+        let cols = self.cols();
+        self.grid
+            .insert_row(row, (0..cols).map(|_| T::default()).collect());
+    }
+
+    pub fn insert_col(&mut self, col: usize) {
+        let rows = self.rows();
+        self.grid
+            .insert_col(col, (0..rows).map(|_| T::default()).collect());
+    }
+}
+impl<T> Grid2d<T> {
+    pub fn rows(&self) -> usize {
+        self.grid.rows()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.grid.cols()
+    }
+
+    pub fn contains(&self, pos: Pos) -> bool {
+        pos.row < self.rows() && pos.col < self.cols()
+    }
+
+    pub fn get(&self, pos: Pos) -> Option<&T> {
+        self.grid.get(pos.row, pos.col)
+    }
+
+    pub fn get_mut(&mut self, pos: Pos) -> Option<&mut T> {
+        self.grid.get_mut(pos.row, pos.col)
+    }
+
+    pub fn remove_row(&mut self, row: usize) -> Option<Vec<T>> {
+        self.grid.remove_row(row)
+    }
+
+    pub fn remove_col(&mut self, col: usize) -> Option<Vec<T>> {
+        self.grid.remove_col(col)
+    }
+
+    pub fn neighbours_with<'a>(
+        &'a self,
+        pos: Pos,
+        offsets: &'a [(isize, isize)],
+    ) -> impl Iterator<Item = Pos> + 'a {
+        offsets
+            .iter()
+            .filter_map(move |(row, col)| pos.offset(*row, *col))
+            .filter(|x| self.contains(*x))
+    }
+
+    pub fn neighbours4(&self, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
+        self.neighbours_with(pos, &ORTHOGONAL_OFFSETS)
+    }
+
+    pub fn neighbours8(&self, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
+        self.neighbours_with(pos, &ALL_OFFSETS)
+    }
+
+    // Row-major positions in the inclusive rectangle, clipped to the grid.
+    pub fn region(&self, top_left: Pos, bottom_right: Pos) -> impl Iterator<Item = Pos> {
+        let rows = top_left.row..=bottom_right.row.min(self.rows().saturating_sub(1));
+        let cols = top_left.col..=bottom_right.col.min(self.cols().saturating_sub(1));
+        let empty = self.rows() == 0 || self.cols() == 0;
+        rows.filter(move |_| !empty)
+            .flat_map(move |row| cols.clone().map(move |col| Pos::new(row, col)))
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = Pos> {
+        let cols = self.cols();
+        (0..self.rows()).flat_map(move |row| (0..cols).map(move |col| Pos::new(row, col)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Pos, &T)> + '_ {
+        self.grid
+            .indexed_iter()
+            .map(|((row, col), cell)| (Pos::new(row, col), cell))
+    }
+
+    pub fn row(&self, row: usize) -> impl Iterator<Item = (Pos, &T)> + '_ {
+        self.grid
+            .iter_row(row)
+            .enumerate()
+            .map(move |(col, cell)| (Pos::new(row, col), cell))
+    }
+}
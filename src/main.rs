@@ -8,6 +8,7 @@ mod day5;
 mod day6;
 mod day7;
 mod day8;
+mod grid2d;
 
 fn main() {
     day1::day1();